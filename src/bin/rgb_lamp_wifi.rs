@@ -13,7 +13,9 @@ use esp_alloc::heap_allocator;
 use esp_backtrace as _;
 use esp_hal::analog::adc::{Adc, AdcConfig, Attenuation};
 use esp_hal::gpio::{Input, InputConfig, Pull};
+use esp_hal::peripherals::GPIO4;
 use esp_hal::timer::timg::TimerGroup;
+use esp_radio::wifi::WifiState;
 use esp_storage::FlashStorage;
 
 #[cfg(feature = "defmt")]
@@ -21,13 +23,12 @@ use defmt::{error, info};
 #[cfg(feature = "log")]
 use log::{error, info};
 
-use embassy_futures::select::{Either, Either3, select, select3};
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::channel::Channel;
-use embassy_time::Timer;
+use embassy_futures::select::{Either, Either4, select, select4};
+use embassy_time::{Duration, Instant, Timer};
 
 use rs_matter_embassy::epoch::epoch;
 use rs_matter_embassy::matter::dm::clusters::desc::{self, ClusterHandler as _};
+use rs_matter_embassy::matter::dm::clusters::identify;
 use rs_matter_embassy::matter::dm::clusters::level_control::{
     self, AttributeDefaults, ClusterAsyncHandler as _, LevelControlHandler, OptionsBitmap,
 };
@@ -51,14 +52,51 @@ use rs_matter_embassy::wireless::{EmbassyWifi, EmbassyWifiMatterStack};
 use embassy_embedded_hal::adapter::BlockingAsync;
 
 use matter_rgb_lamp::dm::color_control::{self, ClusterHandler as _};
-use matter_rgb_lamp::led::led_driver;
-
+use matter_rgb_lamp::dm::lamp_maintenance::{self, ClusterHandler as _};
+use matter_rgb_lamp::dm::lamp_notification::{self, ClusterHandler as _};
+use matter_rgb_lamp::led::led_attributes::{LightAttributes, persist_light_attributes};
+use matter_rgb_lamp::led::led_calibration::{Calibration, persist_calibration};
+use matter_rgb_lamp::led::led_channel::LedChannel;
+use matter_rgb_lamp::led::led_compositor::Overlay;
+use matter_rgb_lamp::led::led_curve::{BrightnessCurve, Curve, persist_brightness_curve};
+use matter_rgb_lamp::led::led_driver::{self, ControlMessage};
+use matter_rgb_lamp::led::led_frame::FrameMonitor;
+use matter_rgb_lamp::led::led_indication::Indication;
+#[cfg(not(any(feature = "rgbw", feature = "pwm-rgb", feature = "pwm-cct")))]
+use matter_rgb_lamp::led::led_output::SmartLedOutput;
+#[cfg(feature = "rgbw")]
+use matter_rgb_lamp::led::led_output::SmartLedRgbwOutput;
+#[cfg(any(feature = "rgbw", feature = "pwm-cct"))]
+use matter_rgb_lamp::led::led_output::WhiteChannel;
+#[cfg(any(feature = "pwm-rgb", feature = "pwm-cct"))]
+use matter_rgb_lamp::led::led_pwm::{self, Polarity, PwmConfig, PwmOutput};
+
+use matter_rgb_lamp::input::input_analog::AdcInput;
+use matter_rgb_lamp::input::input_button::ActiveLow;
+#[cfg(feature = "encoder")]
+use matter_rgb_lamp::input::input_encoder::{EncoderControl, QuadratureEncoder};
+use matter_rgb_lamp::input::input_range::{InputRange, persist_input_range};
 use matter_rgb_lamp::led::led_handler::LedHandler;
+use matter_rgb_lamp::led::led_power::{PixelLeds, PowerBudget};
+use matter_rgb_lamp::led::led_status::{
+    DeviceStatus, StatusIndicator, StatusSuppressed, persist_status_suppressed,
+};
+use matter_rgb_lamp::reset::Resettable;
+use matter_rgb_lamp::settings::Settings;
 
 extern crate alloc;
 
 const BUMP_SIZE: usize = 18000;
 
+/// The lamp's handler, with its on/off button pulled up and its potentiometer on GPIO4.
+type LampHandler<'a> = LedHandler<'a, ActiveLow<Input<'a>>, AdcInput<'a, GPIO4<'a>>>;
+
+// The LED outputs drive different hardware, and both PWM outputs use GPIO18 and GPIO19.
+#[cfg(all(feature = "rgbw", any(feature = "pwm-rgb", feature = "pwm-cct")))]
+compile_error!("The `rgbw` feature cannot be combined with `pwm-rgb` or `pwm-cct`");
+#[cfg(all(feature = "pwm-rgb", feature = "pwm-cct"))]
+compile_error!("The `pwm-rgb` and `pwm-cct` features cannot be combined");
+
 #[cfg(feature = "esp32")]
 const HEAP_SIZE: usize = 40 * 1024; // 40KB for ESP32, which has a disjoint heap
 #[cfg(any(feature = "esp32c3", feature = "esp32h2"))]
@@ -68,6 +106,8 @@ const HEAP_SIZE: usize = 186 * 1024;
 
 esp_bootloader_esp_idf::esp_app_desc!();
 
+/// Returns the store on the NVS partition, holding both the Matter stack's state and the
+/// application settings.
 fn get_persistent_store() -> impl KvBlobStore {
     use esp_bootloader_esp_idf::partitions::{
         DataPartitionSubType, PARTITION_TABLE_MAX_LEN, PartitionType, read_partition_table,
@@ -139,19 +179,48 @@ async fn main(_s: Spawner) {
 
     // == Step 3: ==
     // Set up Matter data model handler
-    let channel = Channel::<CriticalSectionRawMutex, led_driver::ControlMessage, 4>::new();
+    let channel = LedChannel::new();
     let sender = channel.sender();
+    let feedback = led_driver::LedFeedback::new();
+
+    // The settings keep their own keys in the store shared with the Matter stack
+    let settings = Settings::new(get_persistent_store());
 
-    let button_on_off = Input::new(
+    let button_on_off = ActiveLow(Input::new(
         peripherals.GPIO7,
         InputConfig::default().with_pull(Pull::Up),
-    );
+    ));
 
     let mut adc1_config = AdcConfig::new();
     let pin = adc1_config.enable_pin(peripherals.GPIO4, Attenuation::_11dB);
     let adc1 = Adc::new(peripherals.ADC1, adc1_config);
 
-    let led_handler = LedHandler::new(sender, button_on_off, adc1, pin);
+    let knob = AdcInput::new(adc1, pin);
+
+    let knob_range = settings
+        .load::<InputRange>()
+        .await
+        .unwrap_or(LampHandler::DEFAULT_KNOB_RANGE);
+
+    // The OnOff and LevelControl attributes as they were when power was lost
+    let attributes = settings.load::<LightAttributes>().await.unwrap_or_default();
+
+    // Rendering statistics of the LED driver, reported through the LampMaintenance cluster
+    let frame_monitor = FrameMonitor::new();
+
+    let status_indicator =
+        StatusIndicator::new(sender).with_suppressed(settings.load().await.unwrap_or_default());
+
+    let led_handler: LampHandler = LedHandler::new(sender, &feedback, button_on_off, knob)
+        .with_knob_range(knob_range)
+        .with_attributes(attributes)
+        .with_frame_monitor(&frame_monitor)
+        .with_status_indicator(&status_indicator);
+
+    let color_control_handler = color_control::ColorControlHandler::new(
+        Dataver::new_rand(stack.matter().rand()),
+        &led_handler,
+    );
 
     let on_off_handler = OnOffHandler::new(
         Dataver::new_rand(stack.matter().rand()),
@@ -165,10 +234,14 @@ async fn main(_s: Spawner) {
         AttributeDefaults {
             on_level: Nullable::none(),
             options: OptionsBitmap::EXECUTE_IF_OFF,
+            on_off_transition_time: LampHandler::DEFAULT_ON_OFF_TRANSITION_TIME,
             ..Default::default()
         },
     );
 
+    led_handler.set_on_off_transitions(&level_control_handler);
+
+    // Applies StartUpOnOff and StartUpCurrentLevel to the restored attributes.
     on_off_handler.init(Some(&level_control_handler));
     level_control_handler.init(Some(&on_off_handler));
 
@@ -177,24 +250,54 @@ async fn main(_s: Spawner) {
         .chain(
             EpClMatcher::new(
                 Some(LIGHT_ENDPOINT_ID),
-                Some(OnOffHandler::<LedHandler, LedHandler>::CLUSTER.id),
+                Some(OnOffHandler::<LampHandler, LampHandler>::CLUSTER.id),
             ),
             on_off::HandlerAsyncAdaptor(&on_off_handler),
         )
         .chain(
             EpClMatcher::new(
                 Some(LIGHT_ENDPOINT_ID),
-                Some(LevelControlHandler::<LedHandler, LedHandler>::CLUSTER.id),
+                Some(LevelControlHandler::<LampHandler, LampHandler>::CLUSTER.id),
             ),
             level_control::HandlerAsyncAdaptor(&level_control_handler),
         )
         .chain(
             EpClMatcher::new(
                 Some(LIGHT_ENDPOINT_ID),
-                Some(color_control::ColorControlHandler::<LedHandler>::CLUSTER.id),
+                Some(color_control::ColorControlHandler::<LampHandler>::CLUSTER.id),
+            ),
+            Async(color_control::HandlerAdaptor(&color_control_handler)),
+        )
+        .chain(
+            EpClMatcher::new(
+                Some(LIGHT_ENDPOINT_ID),
+                Some(lamp_notification::LampNotificationHandler::<LampHandler>::CLUSTER.id),
+            ),
+            Async(
+                lamp_notification::LampNotificationHandler::new(
+                    Dataver::new_rand(stack.matter().rand()),
+                    &led_handler,
+                )
+                .adapt(),
+            ),
+        )
+        .chain(
+            EpClMatcher::new(
+                Some(LIGHT_ENDPOINT_ID),
+                Some(lamp_maintenance::LampMaintenanceHandler::<LampHandler>::CLUSTER.id),
+            ),
+            Async(
+                lamp_maintenance::LampMaintenanceHandler::new(
+                    Dataver::new_rand(stack.matter().rand()),
+                    &led_handler,
+                )
+                .adapt(),
             ),
+        )
+        .chain(
+            EpClMatcher::new(Some(LIGHT_ENDPOINT_ID), Some(identify::CLUSTER.id)),
             Async(
-                color_control::ColorControlHandler::new(
+                identify::IdentifyHandler::new_with(
                     Dataver::new_rand(stack.matter().rand()),
                     &led_handler,
                 )
@@ -214,7 +317,7 @@ async fn main(_s: Spawner) {
     // `EmbassyPersist`+`EmbassyKvBlobStore` saves to a user-supplied NOR Flash region
     // However, for this demo and for simplicity, we use a dummy persister that does nothing
     let persist = stack
-        .create_persist_with_comm_window(get_persistent_store())
+        .create_persist_with_comm_window(settings.shared_store())
         .await
         .unwrap();
 
@@ -236,8 +339,141 @@ async fn main(_s: Spawner) {
     // == Step 5: ==
     // Setup the LED driver
     let receiver = channel.receiver();
-    let led_driver = led_driver::Driver::new(peripherals.RMT, peripherals.GPIO8.into(), receiver);
+    #[cfg(not(any(feature = "rgbw", feature = "pwm-rgb", feature = "pwm-cct")))]
+    let led_output = SmartLedOutput::new(peripherals.RMT, peripherals.GPIO8.into());
+    #[cfg(feature = "rgbw")]
+    let led_output = SmartLedRgbwOutput::new(
+        peripherals.RMT,
+        peripherals.GPIO8.into(),
+        WhiteChannel::from_kelvin(RGBW_WHITE_KELVIN),
+    );
+    #[cfg(any(feature = "pwm-rgb", feature = "pwm-cct"))]
+    let ledc = led_pwm::new_ledc(peripherals.LEDC);
+    #[cfg(any(feature = "pwm-rgb", feature = "pwm-cct"))]
+    let pwm_timer = led_pwm::new_timer(
+        &ledc,
+        esp_hal::ledc::timer::Number::Timer0,
+        PwmConfig::default(),
+    )
+    .expect("Failed to configure LEDC timer");
+    #[cfg(feature = "pwm-rgb")]
+    let led_output = PwmOutput::rgb(
+        &ledc,
+        &pwm_timer,
+        peripherals.GPIO18,
+        peripherals.GPIO19,
+        peripherals.GPIO20,
+        Polarity::ActiveHigh,
+    )
+    .expect("Failed to configure LEDC channels");
+    #[cfg(feature = "pwm-cct")]
+    let led_output = PwmOutput::cct(
+        &ledc,
+        &pwm_timer,
+        peripherals.GPIO18,
+        peripherals.GPIO19,
+        WhiteChannel::from_kelvin(PWM_COLD_WHITE_KELVIN),
+        WhiteChannel::from_kelvin(PWM_WARM_WHITE_KELVIN),
+        Polarity::ActiveHigh,
+    )
+    .expect("Failed to configure LEDC channels");
+
+    #[cfg(not(any(feature = "rgbw", feature = "pwm-cct")))]
+    let leds = PixelLeds::Rgb {
+        rgb_ma: [LED_CHANNEL_MA; 3],
+    };
+    // The white LED of the RGBW pixel draws as much as each of its colour LEDs.
+    #[cfg(feature = "rgbw")]
+    let leds = PixelLeds::Rgbw {
+        rgb_ma: [LED_CHANNEL_MA; 3],
+        white: WhiteChannel::from_kelvin(RGBW_WHITE_KELVIN),
+        white_ma: LED_CHANNEL_MA,
+    };
+    #[cfg(feature = "pwm-cct")]
+    let leds = PixelLeds::Cct {
+        cold: WhiteChannel::from_kelvin(PWM_COLD_WHITE_KELVIN),
+        warm: WhiteChannel::from_kelvin(PWM_WARM_WHITE_KELVIN),
+        white_ma: [LED_CHANNEL_MA; 2],
+    };
+    let power_budget = PowerBudget {
+        leds,
+        idle_ma: LED_IDLE_MA,
+        led_count: LED_COUNT,
+        limit_ma: LED_POWER_LIMIT_MA,
+    };
+
+    let calibration = settings.load::<Calibration>().await.unwrap_or_default();
+    // The brightness curve last set by a controller, if any
+    let curve = settings
+        .load::<BrightnessCurve>()
+        .await
+        .unwrap_or(BRIGHTNESS_CURVE);
+    let led_driver = led_driver::Driver::new(led_output, calibration, receiver, &feedback)
+        .with_brightness_curve(curve)
+        .with_power_budget(power_budget)
+        .with_frame_rate(LED_FRAME_RATE)
+        .with_frame_monitor(&frame_monitor);
     let mut led_task = pin!(led_driver.run());
+    let mut calibration_task = pin!(persist_calibration(&settings, &feedback, calibration));
+    let mut knob_range_task = pin!(persist_input_range(
+        &settings,
+        led_handler.knob_range_updates()
+    ));
+    let mut attributes_task = pin!(persist_light_attributes(
+        &settings,
+        led_handler.attribute_updates()
+    ));
+    let mut curve_task = pin!(persist_brightness_curve(
+        &settings,
+        led_handler.curve_updates()
+    ));
+
+    // A commissioned lamp only reports the network lost once it was connected, or once joining
+    // takes too long.
+    let started = Instant::now();
+    let mut joined = false;
+    let mut status_task = pin!(status_indicator.run(move || {
+        let matter = stack.matter();
+        if matter.comm_window_state().is_open() {
+            DeviceStatus::Commissioning
+        } else if !matter.has_fabrics() {
+            DeviceStatus::Uncommissioned
+        } else if esp_radio::wifi::sta_state() == WifiState::StaConnected {
+            joined = true;
+            DeviceStatus::Connected
+        } else if joined || started.elapsed() > JOIN_TIMEOUT {
+            DeviceStatus::NetworkLost
+        } else {
+            DeviceStatus::Joining
+        }
+    }));
+    let mut suppressed_task = pin!(persist_status_suppressed(&settings, &status_indicator));
+
+    let mut colour_task = pin!(color_control_handler.run());
+
+    // Setup the rotary encoder, with its A and B pins and push button pulled up
+    #[cfg(feature = "encoder")]
+    let mut encoder_control = EncoderControl::new(
+        QuadratureEncoder::new(
+            Input::new(
+                peripherals.GPIO5,
+                InputConfig::default().with_pull(Pull::Up),
+            ),
+            Input::new(
+                peripherals.GPIO6,
+                InputConfig::default().with_pull(Pull::Up),
+            ),
+            4,
+        ),
+        ActiveLow(Input::new(
+            peripherals.GPIO10,
+            InputConfig::default().with_pull(Pull::Up),
+        )),
+    );
+    #[cfg(feature = "encoder")]
+    let mut encoder_task = pin!(encoder_control.run(&led_handler));
+    #[cfg(not(feature = "encoder"))]
+    let mut encoder_task = pin!(core::future::pending::<()>());
 
     // == Step 6: ==
     // Setup reset button
@@ -250,15 +486,59 @@ async fn main(_s: Spawner) {
     let mut reset_button_task = async || {
         loop {
             button_reset.wait_for_falling_edge().await;
-            match select(button_reset.wait_for_rising_edge(), Timer::after_secs(3)).await {
-                Either::First(_) => (),
+            sender.send(ControlMessage::Indicate {
+                overlay: Overlay::Maintenance,
+                indication: Some(Indication::Countdown {
+                    duration: FACTORY_RESET_HOLD_TIME,
+                }),
+            });
+
+            match select(
+                button_reset.wait_for_rising_edge(),
+                Timer::after(FACTORY_RESET_HOLD_TIME),
+            )
+            .await
+            {
+                Either::First(_) => sender.send(ControlMessage::Indicate {
+                    overlay: Overlay::Maintenance,
+                    indication: None,
+                }),
                 Either::Second(_) => {
                     info!("Performing factory reset...");
-                    if let Err(e) = persist.reset().await {
-                        error!("Factory reset error: {}", e);
+                    match persist.reset().await {
+                        Ok(()) => {
+                            // The driver goes first, as the OnOff and LevelControl attributes
+                            // follow it.
+                            sender.reset();
+                            led_handler.reset();
+                            color_control_handler.reset();
+                            status_indicator.reset();
+
+                            // The LED calibration and brightness curve are kept, as they belong
+                            // to the device rather than to the user.
+                            for result in [
+                                settings.remove::<InputRange>().await,
+                                settings.remove::<LightAttributes>().await,
+                                settings.remove::<StatusSuppressed>().await,
+                            ] {
+                                if let Err(e) = result {
+                                    error!("Failed to clear setting: {}", e);
+                                }
+                            }
+
+                            sender.send(ControlMessage::Indicate {
+                                overlay: Overlay::Maintenance,
+                                indication: Some(Indication::ResetConfirmed),
+                            });
+                        }
+                        Err(e) => {
+                            error!("Factory reset error: {}", e);
+                            sender.send(ControlMessage::Indicate {
+                                overlay: Overlay::Maintenance,
+                                indication: None,
+                            });
+                        }
                     };
-                    // todo reset non-volatile attributes.
-                    // todo Consider adding a `reset()` method to the rs-matter handlers.
                 }
             }
         }
@@ -266,16 +546,107 @@ async fn main(_s: Spawner) {
 
     // == Step 7: ==
     // Run async tasks
-    match select3(&mut matter, &mut led_task, &mut pin!(reset_button_task())).await {
-        Either3::First(r) => {
+    match select4(
+        &mut matter,
+        &mut led_task,
+        &mut pin!(reset_button_task()),
+        select4(
+            select4(
+                &mut calibration_task,
+                &mut knob_range_task,
+                &mut attributes_task,
+                &mut curve_task,
+            ),
+            select(&mut status_task, &mut suppressed_task),
+            &mut colour_task,
+            &mut encoder_task,
+        ),
+    )
+    .await
+    {
+        Either4::First(r) => {
             panic!("Matter thread exited! {:?}", r)
         }
-        Either3::Second(_) => {
+        Either4::Second(_) => {
             panic!("LED thread exited!")
         }
-        Either3::Third(_) => {
+        Either4::Third(_) => {
             panic!("Reset button thread exited!")
         }
+        Either4::Fourth(Either4::First(_)) => {
+            panic!("Settings thread exited!")
+        }
+        Either4::Fourth(Either4::Second(_)) => {
+            panic!("Status thread exited!")
+        }
+        Either4::Fourth(Either4::Third(_)) => {
+            panic!("Colour control thread exited!")
+        }
+        Either4::Fourth(Either4::Fourth(_)) => {
+            panic!("Encoder thread exited!")
+        }
+    }
+}
+
+/// Time a commissioned lamp waits for the network after starting before reporting it lost.
+const JOIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time the reset button has to be held for to perform a factory reset.
+const FACTORY_RESET_HOLD_TIME: Duration = Duration::from_secs(3);
+
+/// Perceptually even dimming, with the lowest level still bright enough for a night light.
+const BRIGHTNESS_CURVE: BrightnessCurve = BrightnessCurve {
+    curve: Curve::Cie1931,
+    min_output: u16::MAX / 100,
+    max_output: u16::MAX,
+};
+
+/// Frames per second at which the LED renders transitions and effects. The LampMaintenance
+/// `FrameRate` attribute changes it until the next restart.
+const LED_FRAME_RATE: u32 = 50;
+
+/// Number of pixels, or of LEDs on each PWM channel, showing the colour. Boards with more set
+/// the `LED_COUNT` environment variable when building.
+const LED_COUNT: u16 = build_setting(option_env!("LED_COUNT"), 1) as u16;
+
+/// Maximum current of all LEDs together, in mA. Defaults to the share of the USB supply they
+/// may use, boards with their own supply set the `LED_POWER_LIMIT_MA` environment variable when
+/// building.
+const LED_POWER_LIMIT_MA: u32 = build_setting(option_env!("LED_POWER_LIMIT_MA"), 400);
+
+/// Current drawn by each LED channel at full drive, in mA.
+#[cfg(not(any(feature = "pwm-rgb", feature = "pwm-cct")))]
+const LED_CHANNEL_MA: u16 = 12;
+/// Analog boards drive each channel through a MOSFET, so are rated far higher than a pixel.
+#[cfg(any(feature = "pwm-rgb", feature = "pwm-cct"))]
+const LED_CHANNEL_MA: u16 = 150;
+
+/// Current drawn by the controller of each pixel while dark, in mA.
+#[cfg(not(any(feature = "pwm-rgb", feature = "pwm-cct")))]
+const LED_IDLE_MA: u16 = 1;
+/// Analog LEDs draw nothing while dark.
+#[cfg(any(feature = "pwm-rgb", feature = "pwm-cct"))]
+const LED_IDLE_MA: u16 = 0;
+
+/// Colour temperature of the white LED in the RGBW pixel, in Kelvin.
+#[cfg(feature = "rgbw")]
+const RGBW_WHITE_KELVIN: u16 = 4000;
+
+/// Colour temperatures of the cold and warm white LEDs of a tunable-white board, in Kelvin.
+#[cfg(feature = "pwm-cct")]
+const PWM_COLD_WHITE_KELVIN: u16 = 6500;
+#[cfg(feature = "pwm-cct")]
+const PWM_WARM_WHITE_KELVIN: u16 = 2700;
+
+/// Parses a board setting given as a decimal environment variable at build time, or returns
+/// `default` if it is not set.
+const fn build_setting(value: Option<&str>, default: u32) -> u32 {
+    match value {
+        Some(value) => match u32::from_str_radix(value, 10) {
+            Ok(value) => value,
+            Err(_) => panic!("Board settings must be decimal numbers"),
+        },
+        None => default,
     }
 }
 
@@ -298,9 +669,12 @@ const NODE: Node = Node {
             device_types: devices!(DEV_TYPE_ENHANCED_COLOR_LIGHT),
             clusters: clusters!(
                 desc::DescHandler::CLUSTER,
-                OnOffHandler::<LedHandler, LedHandler>::CLUSTER,
-                LevelControlHandler::<LedHandler, LedHandler>::CLUSTER
-                color_control::ColorControlHandler::<LedHandler>::CLUSTER
+                identify::CLUSTER,
+                OnOffHandler::<LampHandler, LampHandler>::CLUSTER,
+                LevelControlHandler::<LampHandler, LampHandler>::CLUSTER
+                color_control::ColorControlHandler::<LampHandler>::CLUSTER,
+                lamp_notification::LampNotificationHandler::<LampHandler>::CLUSTER,
+                lamp_maintenance::LampMaintenanceHandler::<LampHandler>::CLUSTER
             ),
         },
     ],
@@ -1,3 +1,5 @@
 use rs_matter_embassy::matter::import;
 
 import!(ColorControl);
+import!(LampNotification; idl = "src/dm/lamp_notification.matter");
+import!(LampMaintenance; idl = "src/dm/lamp_maintenance.matter");
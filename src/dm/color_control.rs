@@ -7,15 +7,27 @@ use rs_matter_embassy::matter::error::{Error, ErrorCode};
 use rs_matter_embassy::matter::tlv::Nullable;
 use rs_matter_embassy::matter::with;
 
-pub use crate::dm::clusters::color_control::ClusterHandler;
 use crate::dm::clusters::color_control::*;
+pub use crate::dm::clusters::color_control::{ClusterHandler, HandlerAdaptor};
+use crate::led::led_colour::{hue_to_xy, xy_to_hue};
+use crate::reset::Resettable;
+
+/// Changes requested by the hooks from outside of Matter, e.g. by a rotary encoder, which are
+/// applied like the equivalent commands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutOfBandMessage {
+    /// Moves the hue of the current colour by `degrees`, at full saturation.
+    StepHue { degrees: i16 },
+    /// Moves the colour temperature by `mireds`, warmer if positive.
+    StepColorTemperature { mireds: i16 },
+}
 
 pub struct ColorControlHandler<T: ColorControlHooks> {
     dataver: Dataver,
     handler: T,
     current_x: Cell<u16>,
     current_y: Cell<u16>,
-    color_mode: ColorMode,
+    color_mode: Cell<ColorMode>,
     options: OptionsBitmap,
     number_of_primes: u8,
     primary_1_x: u16,
@@ -29,8 +41,7 @@ pub struct ColorControlHandler<T: ColorControlHooks> {
     primary_3_intensity: u8,
     // enhanced_color_mode: , // todo EnhancedColorModeEnum is not defined.
     // color_capabilities: ColorCapabilitiesBitmap,
-    remaining_time: u16,
-    color_temperature_mireds: u16,
+    color_temperature_mireds: Cell<u16>,
     color_temp_physical_max_mireds: u16,
     color_temp_physical_min_mireds: u16,
     couple_color_temp_to_level_min_mireds: u16,
@@ -38,13 +49,27 @@ pub struct ColorControlHandler<T: ColorControlHooks> {
 }
 
 impl<T: ColorControlHooks> ColorControlHandler<T> {
+    /// The coolest supported colour temperature, 6500 K.
+    const COLOR_TEMP_PHYSICAL_MIN_MIREDS: u16 = 153;
+    /// The warmest supported colour temperature, 2000 K.
+    const COLOR_TEMP_PHYSICAL_MAX_MIREDS: u16 = 500;
+
+    /// The colour until a controller sets one, a white.
+    const DEFAULT_X: u16 = 39518;
+    const DEFAULT_Y: u16 = 21233;
+    /// The colour temperature until a controller sets one, 4000 K.
+    const DEFAULT_MIREDS: u16 = 250;
+
+    /// Transition time of out-of-band steps, in tenths of a second.
+    const STEP_TRANSITION_TIME: u16 = 1;
+
     pub fn new(dataver: Dataver, handler: T) -> Self {
         Self {
             dataver,
             handler,
-            current_x: Cell::new(39518), // white
-            current_y: Cell::new(21233),
-            color_mode: ColorMode::CurrentXAndCurrentY,
+            current_x: Cell::new(Self::DEFAULT_X),
+            current_y: Cell::new(Self::DEFAULT_Y),
+            color_mode: Cell::new(ColorMode::CurrentXAndCurrentY),
             options: OptionsBitmap::empty(),
             number_of_primes: 3,
             primary_1_x: 0,
@@ -56,10 +81,9 @@ impl<T: ColorControlHooks> ColorControlHandler<T> {
             primary_3_x: 0,
             primary_3_y: 0,
             primary_3_intensity: 0,
-            remaining_time: 0,
-            color_temperature_mireds: 0,
-            color_temp_physical_max_mireds: 0,
-            color_temp_physical_min_mireds: 0,
+            color_temperature_mireds: Cell::new(Self::DEFAULT_MIREDS),
+            color_temp_physical_max_mireds: Self::COLOR_TEMP_PHYSICAL_MAX_MIREDS,
+            color_temp_physical_min_mireds: Self::COLOR_TEMP_PHYSICAL_MIN_MIREDS,
             couple_color_temp_to_level_min_mireds: 0,
             start_up_color_temperature_mireds: 0,
         }
@@ -69,6 +93,57 @@ impl<T: ColorControlHooks> ColorControlHandler<T> {
     pub const fn adapt(self) -> HandlerAdaptor<Self> {
         HandlerAdaptor(self)
     }
+
+    /// Runs the hooks, applying the out-of-band messages they send.
+    pub async fn run(&self) {
+        self.handler
+            .run(|message| {
+                if let Err(e) = self.handle_out_of_band(message) {
+                    warn!("ColorControl: Out-of-band message failed: {}", e);
+                }
+            })
+            .await
+    }
+
+    fn handle_out_of_band(&self, message: OutOfBandMessage) -> Result<(), Error> {
+        match message {
+            OutOfBandMessage::StepHue { degrees } => {
+                let hue = xy_to_hue(self.current_x.get(), self.current_y.get());
+                let (x, y) = hue_to_xy(hue + degrees as f32);
+                self.handler.set_color(x, y, Self::STEP_TRANSITION_TIME)?;
+
+                self.current_x.set(x);
+                self.current_y.set(y);
+                self.color_mode.set(ColorMode::CurrentXAndCurrentY);
+            }
+            OutOfBandMessage::StepColorTemperature { mireds } => {
+                let mireds = (self.color_temperature_mireds.get() as i32 + mireds as i32).clamp(
+                    Self::COLOR_TEMP_PHYSICAL_MIN_MIREDS as i32,
+                    Self::COLOR_TEMP_PHYSICAL_MAX_MIREDS as i32,
+                ) as u16;
+                self.handler
+                    .set_color_temperature(mireds, Self::STEP_TRANSITION_TIME)?;
+
+                self.color_temperature_mireds.set(mireds);
+                self.color_mode.set(ColorMode::ColorTemperature);
+            }
+        }
+
+        self.dataver_changed();
+        Ok(())
+    }
+}
+
+impl<T: ColorControlHooks> Resettable for ColorControlHandler<T> {
+    /// Returns the colour attributes to their defaults. The LED is reset separately.
+    fn reset(&self) {
+        self.current_x.set(Self::DEFAULT_X);
+        self.current_y.set(Self::DEFAULT_Y);
+        self.color_mode.set(ColorMode::CurrentXAndCurrentY);
+        self.color_temperature_mireds.set(Self::DEFAULT_MIREDS);
+        self.dataver_changed();
+        info!("ColorControl: Attributes reset");
+    }
 }
 
 impl<T: ColorControlHooks> ClusterHandler for ColorControlHandler<T> {
@@ -104,6 +179,7 @@ impl<T: ColorControlHooks> ClusterHandler for ColorControlHandler<T> {
         .with_cmds(with!(
             CommandId::MoveToColor
                 | CommandId::MoveColor
+                | CommandId::MoveToColorTemperature
                 | CommandId::StepColor
                 | CommandId::StopMoveStep
                 | CommandId::MoveColorTemperature
@@ -175,12 +251,12 @@ impl<T: ColorControlHooks> ClusterHandler for ColorControlHandler<T> {
 
     fn remaining_time(&self, _ctx: impl ReadContext) -> Result<u16, Error> {
         info!("ColorControl: Called remaining_time()");
-        Ok(self.remaining_time)
+        Ok(self.handler.remaining_time())
     }
 
     fn color_temperature_mireds(&self, _ctx: impl ReadContext) -> Result<u16, Error> {
         info!("ColorControl: Called color_temperature_mireds()");
-        Ok(self.color_temperature_mireds)
+        Ok(self.color_temperature_mireds.get())
     }
 
     fn color_temp_physical_max_mireds(&self, _ctx: impl ReadContext) -> Result<u16, Error> {
@@ -208,7 +284,7 @@ impl<T: ColorControlHooks> ClusterHandler for ColorControlHandler<T> {
 
     fn color_mode(&self, _ctx: impl ReadContext) -> Result<u8, Error> {
         info!("ColorControl: Called color_mode()");
-        Ok(self.color_mode as u8)
+        Ok(self.color_mode.get() as u8)
     }
 
     fn options(&self, _ctx: impl ReadContext) -> Result<u8, Error> {
@@ -309,11 +385,15 @@ impl<T: ColorControlHooks> ClusterHandler for ColorControlHandler<T> {
     ) -> Result<(), Error> {
         info!("ColorControl: Called handle_move_to_color()");
         // todo process options
-        self.handler
-            .set_color(request.color_x()?, request.color_y()?)?;
+        self.handler.set_color(
+            request.color_x()?,
+            request.color_y()?,
+            request.transition_time()?,
+        )?;
 
         self.current_x.set(request.color_x()?);
         self.current_y.set(request.color_y()?);
+        self.color_mode.set(ColorMode::CurrentXAndCurrentY);
         Ok(())
     }
 
@@ -340,10 +420,19 @@ impl<T: ColorControlHooks> ClusterHandler for ColorControlHandler<T> {
     fn handle_move_to_color_temperature(
         &self,
         _ctx: impl InvokeContext,
-        _request: MoveToColorTemperatureRequest<'_>,
+        request: MoveToColorTemperatureRequest<'_>,
     ) -> Result<(), Error> {
         info!("ColorControl: Called handle_move_to_color_temperature()");
-        warn!("Not yet implemented. Doing nothing.");
+        // todo process options
+        let mireds = request.color_temperature_mireds()?.clamp(
+            Self::COLOR_TEMP_PHYSICAL_MIN_MIREDS,
+            Self::COLOR_TEMP_PHYSICAL_MAX_MIREDS,
+        );
+        self.handler
+            .set_color_temperature(mireds, request.transition_time()?)?;
+
+        self.color_temperature_mireds.set(mireds);
+        self.color_mode.set(ColorMode::ColorTemperature);
         Ok(())
     }
 
@@ -423,16 +512,42 @@ impl<T: ColorControlHooks> ClusterHandler for ColorControlHandler<T> {
     }
 }
 
+#[allow(async_fn_in_trait)]
 pub trait ColorControlHooks {
-    // todo add the transition time
-    fn set_color(&self, x: u16, y: u16) -> Result<(), Error>;
+    /// Moves the light to the `x`, `y` colour over `transition_time` tenths of a second.
+    fn set_color(&self, x: u16, y: u16, transition_time: u16) -> Result<(), Error>;
+
+    /// Moves the light to the colour temperature `mireds` over `transition_time` tenths of a
+    /// second.
+    fn set_color_temperature(&self, mireds: u16, transition_time: u16) -> Result<(), Error>;
+
+    /// Time left until the current colour transition completes, in tenths of a second.
+    fn remaining_time(&self) -> u16;
+
+    /// Runs alongside the cluster, sending [`OutOfBandMessage`]s through `notify` to change the
+    /// colour from outside of Matter.
+    async fn run<F: Fn(OutOfBandMessage)>(&self, _notify: F) {
+        core::future::pending::<()>().await
+    }
 }
 
 impl<T> ColorControlHooks for &T
 where
     T: ColorControlHooks,
 {
-    fn set_color(&self, x: u16, y: u16) -> Result<(), Error> {
-        (*self).set_color(x, y)
+    fn set_color(&self, x: u16, y: u16, transition_time: u16) -> Result<(), Error> {
+        (*self).set_color(x, y, transition_time)
+    }
+
+    fn set_color_temperature(&self, mireds: u16, transition_time: u16) -> Result<(), Error> {
+        (*self).set_color_temperature(mireds, transition_time)
+    }
+
+    fn remaining_time(&self) -> u16 {
+        (*self).remaining_time()
+    }
+
+    async fn run<F: Fn(OutOfBandMessage)>(&self, notify: F) {
+        (*self).run(notify).await
     }
 }
@@ -0,0 +1,283 @@
+use log::info;
+
+use rs_matter_embassy::matter::dm::{Cluster, Dataver, InvokeContext, ReadContext, WriteContext};
+use rs_matter_embassy::matter::error::{Error, ErrorCode};
+use rs_matter_embassy::matter::with;
+
+pub use crate::dm::clusters::lamp_maintenance::ClusterHandler;
+use crate::dm::clusters::lamp_maintenance::*;
+use crate::led::led_curve::{BrightnessCurve, Curve, CurvePoints, MAX_CURVE_POINTS};
+use crate::led::led_frame::FrameStats;
+
+/// Handles the manufacturer-specific `LampMaintenance` cluster.
+pub struct LampMaintenanceHandler<T: LampMaintenanceHooks> {
+    dataver: Dataver,
+    handler: T,
+}
+
+impl<T: LampMaintenanceHooks> LampMaintenanceHandler<T> {
+    pub fn new(dataver: Dataver, handler: T) -> Self {
+        Self { dataver, handler }
+    }
+
+    /// Adapt the handler instance to the generic `rs-matter` `Handler` trait
+    pub const fn adapt(self) -> HandlerAdaptor<Self> {
+        HandlerAdaptor(self)
+    }
+}
+
+impl<T: LampMaintenanceHooks> ClusterHandler for LampMaintenanceHandler<T> {
+    #[doc = "The cluster-metadata corresponding to this handler trait."]
+    const CLUSTER: Cluster<'static> = FULL_CLUSTER
+        .with_revision(1)
+        .with_attrs(with!(required))
+        .with_cmds(with!(
+            CommandId::CalibrateKnob
+                | CommandId::SetChannelCalibration
+                | CommandId::ResetCalibration
+                | CommandId::SetBrightnessCurve
+                | CommandId::ResetFrameStats
+                | CommandId::SetBrightnessLut
+        ));
+
+    fn dataver(&self) -> u32 {
+        self.dataver.get()
+    }
+
+    fn dataver_changed(&self) {
+        self.dataver.changed();
+    }
+
+    fn power_limited(&self, _ctx: impl ReadContext) -> Result<bool, Error> {
+        Ok(self.handler.power_limited())
+    }
+
+    fn frame_rate(&self, _ctx: impl ReadContext) -> Result<u8, Error> {
+        Ok(self.handler.frame_rate())
+    }
+
+    fn set_frame_rate(&self, _ctx: impl WriteContext, value: u8) -> Result<(), Error> {
+        info!("LampMaintenance: Called set_frame_rate()");
+        if !(1..=100).contains(&value) {
+            return Err(ErrorCode::ConstraintError.into());
+        }
+
+        self.handler.set_frame_rate(value);
+        self.dataver_changed();
+        Ok(())
+    }
+
+    fn rendered_frames(&self, _ctx: impl ReadContext) -> Result<u32, Error> {
+        Ok(self.handler.frame_stats().frames)
+    }
+
+    fn written_frames(&self, _ctx: impl ReadContext) -> Result<u32, Error> {
+        Ok(self.handler.frame_stats().writes)
+    }
+
+    fn dropped_frames(&self, _ctx: impl ReadContext) -> Result<u32, Error> {
+        Ok(self.handler.frame_stats().dropped)
+    }
+
+    fn frame_time(&self, _ctx: impl ReadContext) -> Result<u32, Error> {
+        Ok(self.handler.frame_stats().frame_time.as_micros() as u32)
+    }
+
+    fn max_write_time(&self, _ctx: impl ReadContext) -> Result<u32, Error> {
+        Ok(self.handler.frame_stats().max_write_time.as_micros() as u32)
+    }
+
+    fn status_suppressed(&self, _ctx: impl ReadContext) -> Result<bool, Error> {
+        Ok(self.handler.status_suppressed())
+    }
+
+    fn set_status_suppressed(&self, _ctx: impl WriteContext, value: bool) -> Result<(), Error> {
+        info!("LampMaintenance: Called set_status_suppressed()");
+        self.handler.set_status_suppressed(value);
+        self.dataver_changed();
+        Ok(())
+    }
+
+    fn handle_calibrate_knob(&self, _ctx: impl InvokeContext) -> Result<(), Error> {
+        info!("LampMaintenance: Called handle_calibrate_knob()");
+        self.handler.calibrate_knob()
+    }
+
+    fn handle_set_channel_calibration(
+        &self,
+        _ctx: impl InvokeContext,
+        request: SetChannelCalibrationRequest<'_>,
+    ) -> Result<(), Error> {
+        info!("LampMaintenance: Called handle_set_channel_calibration()");
+
+        let channel = request.channel()?;
+        if channel > 2 {
+            return Err(ErrorCode::ConstraintError.into());
+        }
+
+        self.handler.set_channel_calibration(
+            channel as usize,
+            request.gamma()?,
+            [request.red()?, request.green()?, request.blue()?],
+            request.gain()?,
+        )
+    }
+
+    fn handle_reset_calibration(&self, _ctx: impl InvokeContext) -> Result<(), Error> {
+        info!("LampMaintenance: Called handle_reset_calibration()");
+        self.handler.reset_calibration()
+    }
+
+    fn handle_set_brightness_curve(
+        &self,
+        _ctx: impl InvokeContext,
+        request: SetBrightnessCurveRequest<'_>,
+    ) -> Result<(), Error> {
+        info!("LampMaintenance: Called handle_set_brightness_curve()");
+
+        let curve = BrightnessCurve {
+            curve: match request.curve()? {
+                BrightnessCurveEnum::Linear => Curve::Linear,
+                BrightnessCurveEnum::Cie1931 => Curve::Cie1931,
+                BrightnessCurveEnum::Logarithmic => Curve::Logarithmic,
+            },
+            min_output: request.min_output()?,
+            max_output: request.max_output()?,
+        };
+        if curve.min_output > curve.max_output {
+            return Err(ErrorCode::ConstraintError.into());
+        }
+
+        self.handler.set_brightness_curve(curve)
+    }
+
+    fn handle_reset_frame_stats(&self, _ctx: impl InvokeContext) -> Result<(), Error> {
+        info!("LampMaintenance: Called handle_reset_frame_stats()");
+        self.handler.reset_frame_stats();
+        self.dataver_changed();
+        Ok(())
+    }
+
+    fn handle_set_brightness_lut(
+        &self,
+        _ctx: impl InvokeContext,
+        request: SetBrightnessLutRequest<'_>,
+    ) -> Result<(), Error> {
+        info!("LampMaintenance: Called handle_set_brightness_lut()");
+
+        let mut points = [(0, 0); MAX_CURVE_POINTS];
+        let mut len = 0;
+        for point in request.points()?.iter() {
+            let point = point?;
+            *points.get_mut(len).ok_or(ErrorCode::ConstraintError)? =
+                (point.level()?, point.output()?);
+            len += 1;
+        }
+        let points = CurvePoints::new(&points[..len]).ok_or(ErrorCode::ConstraintError)?;
+
+        let curve = BrightnessCurve {
+            curve: Curve::Lut(points),
+            min_output: request.min_output()?,
+            max_output: request.max_output()?,
+        };
+        if curve.min_output > curve.max_output {
+            return Err(ErrorCode::ConstraintError.into());
+        }
+
+        self.handler.set_brightness_curve(curve)
+    }
+}
+
+pub trait LampMaintenanceHooks {
+    /// Whether the LED output is currently dimmed to stay within its power budget.
+    fn power_limited(&self) -> bool;
+
+    /// Frames per second at which the LED renders transitions and effects.
+    fn frame_rate(&self) -> u8;
+
+    fn set_frame_rate(&self, frame_rate: u8);
+
+    /// The rendering statistics of the LED driver, all zero if they are not collected.
+    fn frame_stats(&self) -> FrameStats;
+
+    fn reset_frame_stats(&self);
+
+    /// Whether the status patterns are hidden.
+    fn status_suppressed(&self) -> bool;
+
+    fn set_status_suppressed(&self, suppressed: bool);
+
+    /// Starts learning the end stops of the level potentiometer.
+    fn calibrate_knob(&self) -> Result<(), Error>;
+
+    /// Replaces the correction of the LED `channel`, where 0 is red, 1 green and 2 blue, with
+    /// its `gamma`, its `mix` of the linear red, green and blue, and its `gain`.
+    fn set_channel_calibration(
+        &self,
+        channel: usize,
+        gamma: f32,
+        mix: [f32; 3],
+        gain: f32,
+    ) -> Result<(), Error>;
+
+    /// Returns the LED calibration to its default.
+    fn reset_calibration(&self) -> Result<(), Error>;
+
+    /// Replaces the mapping from level to light output.
+    fn set_brightness_curve(&self, curve: BrightnessCurve) -> Result<(), Error>;
+}
+
+impl<T> LampMaintenanceHooks for &T
+where
+    T: LampMaintenanceHooks,
+{
+    fn power_limited(&self) -> bool {
+        (*self).power_limited()
+    }
+
+    fn frame_rate(&self) -> u8 {
+        (*self).frame_rate()
+    }
+
+    fn set_frame_rate(&self, frame_rate: u8) {
+        (*self).set_frame_rate(frame_rate)
+    }
+
+    fn frame_stats(&self) -> FrameStats {
+        (*self).frame_stats()
+    }
+
+    fn reset_frame_stats(&self) {
+        (*self).reset_frame_stats()
+    }
+
+    fn status_suppressed(&self) -> bool {
+        (*self).status_suppressed()
+    }
+
+    fn set_status_suppressed(&self, suppressed: bool) {
+        (*self).set_status_suppressed(suppressed)
+    }
+
+    fn calibrate_knob(&self) -> Result<(), Error> {
+        (*self).calibrate_knob()
+    }
+
+    fn set_channel_calibration(
+        &self,
+        channel: usize,
+        gamma: f32,
+        mix: [f32; 3],
+        gain: f32,
+    ) -> Result<(), Error> {
+        (*self).set_channel_calibration(channel, gamma, mix, gain)
+    }
+
+    fn reset_calibration(&self) -> Result<(), Error> {
+        (*self).reset_calibration()
+    }
+
+    fn set_brightness_curve(&self, curve: BrightnessCurve) -> Result<(), Error> {
+        (*self).set_brightness_curve(curve)
+    }
+}
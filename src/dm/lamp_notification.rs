@@ -0,0 +1,86 @@
+use log::info;
+
+use rs_matter_embassy::matter::dm::{Cluster, Dataver, InvokeContext};
+use rs_matter_embassy::matter::error::{Error, ErrorCode};
+use rs_matter_embassy::matter::with;
+
+pub use crate::dm::clusters::lamp_notification::ClusterHandler;
+use crate::dm::clusters::lamp_notification::*;
+
+/// How long a notification flash goes on for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlashLength {
+    /// A number of flashes.
+    Count(u16),
+    /// Flashes for the given tenths of a second.
+    Duration(u16),
+}
+
+/// Handles the manufacturer-specific `LampNotification` cluster.
+pub struct LampNotificationHandler<T: LampNotificationHooks> {
+    dataver: Dataver,
+    handler: T,
+}
+
+impl<T: LampNotificationHooks> LampNotificationHandler<T> {
+    pub fn new(dataver: Dataver, handler: T) -> Self {
+        Self { dataver, handler }
+    }
+
+    /// Adapt the handler instance to the generic `rs-matter` `Handler` trait
+    pub const fn adapt(self) -> HandlerAdaptor<Self> {
+        HandlerAdaptor(self)
+    }
+}
+
+impl<T: LampNotificationHooks> ClusterHandler for LampNotificationHandler<T> {
+    #[doc = "The cluster-metadata corresponding to this handler trait."]
+    const CLUSTER: Cluster<'static> = FULL_CLUSTER
+        .with_revision(1)
+        .with_attrs(with!(required))
+        .with_cmds(with!(CommandId::Flash));
+
+    fn dataver(&self) -> u32 {
+        self.dataver.get()
+    }
+
+    fn dataver_changed(&self) {
+        self.dataver.changed();
+    }
+
+    fn handle_flash(
+        &self,
+        _ctx: impl InvokeContext,
+        request: FlashRequest<'_>,
+    ) -> Result<(), Error> {
+        info!("LampNotification: Called handle_flash()");
+
+        let length = match (request.count()?, request.duration()?) {
+            (0, 0) => return Err(ErrorCode::ConstraintError.into()),
+            (0, duration) => FlashLength::Duration(duration),
+            (count, _) => FlashLength::Count(count),
+        };
+        let period = request.period()?;
+        if period == 0 {
+            return Err(ErrorCode::ConstraintError.into());
+        }
+
+        self.handler
+            .flash(request.color_x()?, request.color_y()?, length, period)
+    }
+}
+
+pub trait LampNotificationHooks {
+    /// Flashes the `x`, `y` colour once every `period` tenths of a second, then returns to the
+    /// state the light was in before.
+    fn flash(&self, x: u16, y: u16, length: FlashLength, period: u16) -> Result<(), Error>;
+}
+
+impl<T> LampNotificationHooks for &T
+where
+    T: LampNotificationHooks,
+{
+    fn flash(&self, x: u16, y: u16, length: FlashLength, period: u16) -> Result<(), Error> {
+        (*self).flash(x, y, length, period)
+    }
+}
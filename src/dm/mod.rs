@@ -1,2 +1,4 @@
 pub mod clusters;
 pub mod color_control;
+pub mod lamp_maintenance;
+pub mod lamp_notification;
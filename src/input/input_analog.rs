@@ -0,0 +1,47 @@
+#[cfg(feature = "esp")]
+use embassy_futures::yield_now;
+#[cfg(feature = "esp")]
+use esp_hal::Blocking;
+#[cfg(feature = "esp")]
+use esp_hal::analog::adc::{Adc, AdcChannel, AdcPin};
+#[cfg(feature = "esp")]
+use esp_hal::peripherals::ADC1;
+
+/// An analog input such as a potentiometer.
+#[allow(async_fn_in_trait)]
+pub trait AnalogInput {
+    type Error;
+
+    /// Samples the input, returning the raw reading.
+    async fn read(&mut self) -> Result<u16, Self::Error>;
+}
+
+/// Samples a pin through the ADC1 peripheral.
+#[cfg(feature = "esp")]
+pub struct AdcInput<'a, PIN> {
+    adc: Adc<'a, ADC1<'a>, Blocking>,
+    pin: AdcPin<PIN, ADC1<'a>>,
+}
+
+#[cfg(feature = "esp")]
+impl<'a, PIN: AdcChannel> AdcInput<'a, PIN> {
+    pub fn new(adc: Adc<'a, ADC1<'a>, Blocking>, pin: AdcPin<PIN, ADC1<'a>>) -> Self {
+        Self { adc, pin }
+    }
+}
+
+#[cfg(feature = "esp")]
+impl<PIN: AdcChannel> AnalogInput for AdcInput<'_, PIN> {
+    type Error = ();
+
+    async fn read(&mut self) -> Result<u16, Self::Error> {
+        loop {
+            match self.adc.read_oneshot(&mut self.pin) {
+                Ok(value) => return Ok(value),
+                // The conversion takes a few microseconds, so let other tasks run meanwhile.
+                Err(nb::Error::WouldBlock) => yield_now().await,
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+        }
+    }
+}
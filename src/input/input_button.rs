@@ -0,0 +1,44 @@
+use embedded_hal::digital::InputPin;
+use embedded_hal_async::digital::Wait;
+
+/// A push button.
+#[allow(async_fn_in_trait)]
+pub trait Button {
+    type Error;
+
+    fn is_pressed(&mut self) -> Result<bool, Self::Error>;
+
+    /// Waits until the button is pressed or released. May return on contact bounce, so callers
+    /// should check [`Button::is_pressed`] afterwards.
+    async fn wait_for_change(&mut self) -> Result<(), Self::Error>;
+}
+
+/// A button pulling its pin low while pressed, e.g. to ground against a pull-up.
+pub struct ActiveLow<P>(pub P);
+
+impl<P: InputPin + Wait> Button for ActiveLow<P> {
+    type Error = P::Error;
+
+    fn is_pressed(&mut self) -> Result<bool, Self::Error> {
+        self.0.is_low()
+    }
+
+    async fn wait_for_change(&mut self) -> Result<(), Self::Error> {
+        self.0.wait_for_any_edge().await
+    }
+}
+
+/// A button driving its pin high while pressed, e.g. to the supply against a pull-down.
+pub struct ActiveHigh<P>(pub P);
+
+impl<P: InputPin + Wait> Button for ActiveHigh<P> {
+    type Error = P::Error;
+
+    fn is_pressed(&mut self) -> Result<bool, Self::Error> {
+        self.0.is_high()
+    }
+
+    async fn wait_for_change(&mut self) -> Result<(), Self::Error> {
+        self.0.wait_for_any_edge().await
+    }
+}
@@ -0,0 +1,178 @@
+use core::future::pending;
+
+use embassy_futures::select::{Either, Either3, select, select3};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::digital::InputPin;
+use embedded_hal_async::digital::Wait;
+
+#[cfg(feature = "defmt")]
+use defmt::{error, info};
+#[cfg(feature = "log")]
+use log::{error, info};
+
+use crate::input::input_button::Button;
+use crate::input::input_gesture::{Gesture, GestureConfig, GestureRecognizer};
+
+/// Time after the last turn or click before the encoder returns to its first mode.
+const MODE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Quarter steps for each transition of the A and B signals, indexed by the previous state in
+/// the upper two bits and the current state in the lower two. Invalid transitions, where both
+/// signals changed at once, count as no movement.
+const TRANSITIONS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+/// A rotary encoder.
+#[allow(async_fn_in_trait)]
+pub trait RotaryEncoder {
+    type Error;
+
+    /// Waits until the encoder is turned, returning the detents moved since the last call,
+    /// positive clockwise.
+    async fn wait_for_detents(&mut self) -> Result<i32, Self::Error>;
+}
+
+/// A quadrature encoder decoded from the edges of its A and B pins.
+pub struct QuadratureEncoder<P> {
+    a: P,
+    b: P,
+    state: Option<u8>,
+    steps: i8,
+    steps_per_detent: i8,
+}
+
+impl<P: InputPin + Wait> QuadratureEncoder<P> {
+    /// Creates an encoder advancing `steps_per_detent` quarter steps per click, usually 4.
+    /// Turning clockwise is expected to lead with `a`.
+    pub fn new(a: P, b: P, steps_per_detent: i8) -> Self {
+        Self {
+            a,
+            b,
+            state: None,
+            steps: 0,
+            steps_per_detent: steps_per_detent.max(1),
+        }
+    }
+
+    fn read(&mut self) -> Result<u8, P::Error> {
+        Ok(((self.a.is_high()? as u8) << 1) | self.b.is_high()? as u8)
+    }
+}
+
+impl<P: InputPin + Wait> RotaryEncoder for QuadratureEncoder<P> {
+    type Error = P::Error;
+
+    async fn wait_for_detents(&mut self) -> Result<i32, Self::Error> {
+        loop {
+            let state = self.read()?;
+            if let Some(previous) = self.state.replace(state) {
+                self.steps += TRANSITIONS[((previous << 2) | state) as usize];
+
+                let detents = self.steps / self.steps_per_detent;
+                if detents != 0 {
+                    self.steps -= detents * self.steps_per_detent;
+                    return Ok(detents as i32);
+                }
+            }
+
+            match select(self.a.wait_for_any_edge(), self.b.wait_for_any_edge()).await {
+                Either::First(result) | Either::Second(result) => result?,
+            }
+        }
+    }
+}
+
+/// What turning an [`EncoderControl`] adjusts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncoderMode {
+    Level,
+    Hue,
+    ColourTemperature,
+}
+
+/// Receives the detents turned on an [`EncoderControl`].
+pub trait EncoderTarget {
+    /// Adjusts what `mode` controls by `detents`, positive clockwise.
+    fn step(&self, mode: EncoderMode, detents: i32);
+}
+
+impl<T> EncoderTarget for &T
+where
+    T: EncoderTarget,
+{
+    fn step(&self, mode: EncoderMode, detents: i32) {
+        (*self).step(mode, detents)
+    }
+}
+
+/// A rotary encoder with a push button, where each click switches to the next
+/// [`EncoderMode`].
+pub struct EncoderControl<E: RotaryEncoder, B: Button> {
+    encoder: E,
+    button: GestureRecognizer<B>,
+    modes: &'static [EncoderMode],
+}
+
+impl<E: RotaryEncoder, B: Button> EncoderControl<E, B> {
+    pub fn new(encoder: E, button: B) -> Self {
+        Self {
+            encoder,
+            // Only single clicks are used, so report them without waiting for another.
+            button: GestureRecognizer::new(button, GestureConfig::default().with_max_clicks(1)),
+            modes: &[
+                EncoderMode::Level,
+                EncoderMode::Hue,
+                EncoderMode::ColourTemperature,
+            ],
+        }
+    }
+
+    /// Sets the modes cycled through on each click, starting with the first. Does nothing if
+    /// `modes` is empty.
+    pub fn with_modes(mut self, modes: &'static [EncoderMode]) -> Self {
+        if !modes.is_empty() {
+            self.modes = modes;
+        }
+        self
+    }
+
+    /// Passes each turn of the encoder to `target`, in the mode selected with the button.
+    pub async fn run(&mut self, target: impl EncoderTarget) -> ! {
+        let mut mode = 0;
+
+        loop {
+            let timeout = match mode {
+                0 => None,
+                _ => Some(Instant::now() + MODE_TIMEOUT),
+            };
+            let timeout = async move {
+                match timeout {
+                    Some(deadline) => Timer::at(deadline).await,
+                    None => pending().await,
+                }
+            };
+
+            let event = select3(self.encoder.wait_for_detents(), self.button.next(), timeout).await;
+            match event {
+                Either3::First(Ok(detents)) => target.step(self.modes[mode], detents),
+                Either3::First(Err(_)) => error!("Error reading encoder"),
+                Either3::Second(Ok(Gesture::Click(_))) => {
+                    mode = (mode + 1) % self.modes.len();
+                    info!(
+                        "Encoder adjusts {}",
+                        match self.modes[mode] {
+                            EncoderMode::Level => "level",
+                            EncoderMode::Hue => "hue",
+                            EncoderMode::ColourTemperature => "colour temperature",
+                        }
+                    );
+                }
+                Either3::Second(Ok(_)) => {}
+                Either3::Second(Err(_)) => error!("Error reading encoder button"),
+                Either3::Third(()) => {
+                    info!("Encoder mode timed out");
+                    mode = 0;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use embassy_time::{Duration, Instant};
+
+/// Largest window of [`Smoothing::Median`].
+pub const MAX_MEDIAN_WINDOW: usize = 9;
+
+/// How an [`AnalogFilter`] smooths the raw readings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Smoothing {
+    None,
+    /// Exponential moving average, weighting each new reading by `alpha` / 256.
+    Ema {
+        alpha: u8,
+    },
+    /// Median of the last `window` readings, up to [`MAX_MEDIAN_WINDOW`], which rejects
+    /// spikes without lagging behind steady movement.
+    Median {
+        window: u8,
+    },
+}
+
+/// Configuration of an [`AnalogFilter`].
+#[derive(Clone, Copy, Debug)]
+pub struct FilterConfig {
+    pub smoothing: Smoothing,
+    /// Change of the smoothed value, in raw counts, below which the output stays put.
+    pub hysteresis: u16,
+    /// Interval between readings while the output is changing.
+    pub active_period: Duration,
+    /// Interval between readings once the output settled.
+    pub idle_period: Duration,
+    /// Time without output changes after which the input counts as settled.
+    pub settle_time: Duration,
+}
+
+impl FilterConfig {
+    pub const fn with_smoothing(mut self, smoothing: Smoothing) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    pub const fn with_hysteresis(mut self, hysteresis: u16) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+
+    /// Sets the intervals between readings while the output is changing and once it settled.
+    pub const fn with_sampling(mut self, active_period: Duration, idle_period: Duration) -> Self {
+        self.active_period = active_period;
+        self.idle_period = idle_period;
+        self
+    }
+}
+
+impl Default for FilterConfig {
+    /// An EMA with alpha 0.2, as suits a 12-bit ADC reading a potentiometer.
+    fn default() -> Self {
+        Self {
+            smoothing: Smoothing::Ema { alpha: 51 },
+            hysteresis: 8,
+            active_period: Duration::from_millis(20),
+            idle_period: Duration::from_millis(200),
+            settle_time: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Smooths the raw readings of an analog input, and only reports changes beyond the
+/// hysteresis, sampling faster while the input moves.
+pub struct AnalogFilter {
+    config: FilterConfig,
+    smoothed: Option<u16>,
+    window: [u16; MAX_MEDIAN_WINDOW],
+    next: usize,
+    output: Option<u16>,
+    changed_at: Instant,
+}
+
+impl AnalogFilter {
+    pub fn new(config: FilterConfig) -> Self {
+        Self {
+            config,
+            smoothed: None,
+            window: [0; MAX_MEDIAN_WINDOW],
+            next: 0,
+            output: None,
+            changed_at: Instant::MIN,
+        }
+    }
+
+    /// Starts from `value` rather than ramping towards the first readings. Filters are seeded
+    /// with their first reading otherwise.
+    pub fn seed(&mut self, value: u16) {
+        self.smoothed = Some(value);
+        self.window.fill(value);
+        self.output = Some(value);
+    }
+
+    /// The smoothed value, before the hysteresis is applied.
+    pub fn smoothed(&self) -> Option<u16> {
+        self.smoothed
+    }
+
+    /// The last value reported by [`AnalogFilter::update`].
+    pub fn output(&self) -> Option<u16> {
+        self.output
+    }
+
+    /// Adds a raw reading taken at `now`, returning the new output if it changed.
+    pub fn update(&mut self, raw: u16, now: Instant) -> Option<u16> {
+        let Some(previous) = self.smoothed else {
+            self.seed(raw);
+            self.changed_at = now;
+            return self.output;
+        };
+
+        let smoothed = match self.config.smoothing {
+            Smoothing::None => raw,
+            Smoothing::Ema { alpha } => {
+                let alpha = alpha as u32;
+                ((raw as u32 * alpha + previous as u32 * (256 - alpha) + 128) / 256) as u16
+            }
+            Smoothing::Median { window } => {
+                let window = (window as usize).clamp(1, MAX_MEDIAN_WINDOW);
+                self.window[self.next % window] = raw;
+                self.next = (self.next + 1) % window;
+
+                let mut sorted = [0; MAX_MEDIAN_WINDOW];
+                sorted[..window].copy_from_slice(&self.window[..window]);
+                sorted[..window].sort_unstable();
+                sorted[window / 2]
+            }
+        };
+        self.smoothed = Some(smoothed);
+
+        let output = self.output.unwrap_or(smoothed);
+        if smoothed.abs_diff(output) <= self.config.hysteresis {
+            return None;
+        }
+
+        self.output = Some(smoothed);
+        self.changed_at = now;
+        self.output
+    }
+
+    /// Time to wait before the next reading.
+    pub fn poll_period(&self, now: Instant) -> Duration {
+        if now.saturating_duration_since(self.changed_at) < self.config.settle_time {
+            self.config.active_period
+        } else {
+            self.config.idle_period
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::at;
+
+    #[test]
+    fn first_reading_seeds_the_output() {
+        let mut filter = AnalogFilter::new(FilterConfig::default());
+        assert_eq!(filter.update(1000, at(0)), Some(1000));
+        assert_eq!(filter.smoothed(), Some(1000));
+    }
+
+    #[test]
+    fn hysteresis_holds_small_changes() {
+        let config = FilterConfig::default()
+            .with_smoothing(Smoothing::None)
+            .with_hysteresis(8);
+        let mut filter = AnalogFilter::new(config);
+        filter.update(1000, at(0));
+
+        assert_eq!(filter.update(1005, at(20)), None);
+        assert_eq!(filter.smoothed(), Some(1005));
+        assert_eq!(filter.update(1009, at(40)), Some(1009));
+    }
+
+    #[test]
+    fn ema_weights_new_readings_by_alpha() {
+        let config = FilterConfig::default().with_smoothing(Smoothing::Ema { alpha: 128 });
+        let mut filter = AnalogFilter::new(config);
+        filter.update(0, at(0));
+
+        assert_eq!(filter.update(1000, at(20)), Some(500));
+    }
+
+    #[test]
+    fn median_rejects_spikes() {
+        let config = FilterConfig::default()
+            .with_smoothing(Smoothing::Median { window: 3 })
+            .with_hysteresis(0);
+        let mut filter = AnalogFilter::new(config);
+        filter.update(100, at(0));
+
+        assert_eq!(filter.update(4000, at(20)), None);
+        assert_eq!(filter.update(101, at(40)), Some(101));
+    }
+
+    #[test]
+    fn samples_slower_once_settled() {
+        let config = FilterConfig::default();
+        let mut filter = AnalogFilter::new(config);
+        filter.update(1000, at(0));
+
+        assert_eq!(filter.poll_period(at(500)), config.active_period);
+        assert_eq!(filter.poll_period(at(1000)), config.idle_period);
+    }
+}
@@ -0,0 +1,428 @@
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::input::input_button::Button;
+
+/// Gestures recognised on a [`Button`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    /// Pressed and released the given number of times in quick succession.
+    Click(u8),
+    /// Held down for longer than the hold time. Followed by [`Gesture::Release`].
+    Hold,
+    /// Still held down after the long press time, following [`Gesture::Hold`].
+    LongPress,
+    /// Released after a [`Gesture::Hold`].
+    Release,
+}
+
+/// Timings used to recognise [`Gesture`]s.
+#[derive(Clone, Copy, Debug)]
+pub struct GestureConfig {
+    /// Time the button has to stay in a state before a change is accepted.
+    pub debounce: Duration,
+    /// Longest release between the clicks of a multi-click.
+    pub click_gap: Duration,
+    /// Time after which a press becomes a [`Gesture::Hold`].
+    pub hold_time: Duration,
+    /// Time after which a press becomes a [`Gesture::LongPress`].
+    pub long_press_time: Duration,
+    /// Clicks after which a multi-click is reported without waiting for another.
+    pub max_clicks: u8,
+}
+
+impl GestureConfig {
+    pub const fn with_click_gap(mut self, click_gap: Duration) -> Self {
+        self.click_gap = click_gap;
+        self
+    }
+
+    pub const fn with_hold_time(mut self, hold_time: Duration) -> Self {
+        self.hold_time = hold_time;
+        self
+    }
+
+    pub const fn with_long_press_time(mut self, long_press_time: Duration) -> Self {
+        self.long_press_time = long_press_time;
+        self
+    }
+
+    /// Sets the clicks to count. A single click is reported straight away if `1`, rather than
+    /// after the click gap.
+    pub const fn with_max_clicks(mut self, max_clicks: u8) -> Self {
+        self.max_clicks = if max_clicks == 0 { 1 } else { max_clicks };
+        self
+    }
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            debounce: Duration::from_millis(20),
+            click_gap: Duration::from_millis(300),
+            hold_time: Duration::from_millis(500),
+            long_press_time: Duration::from_secs(2),
+            max_clicks: 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    Released,
+    Pressed { clicks: u8, hold_at: Instant },
+    Between { clicks: u8, click_by: Instant },
+    Held { long_press_at: Option<Instant> },
+}
+
+impl State {
+    /// When the state ends if the button does not change.
+    fn timeout(&self) -> Option<Instant> {
+        match *self {
+            State::Released => None,
+            State::Pressed { hold_at, .. } => Some(hold_at),
+            State::Between { click_by, .. } => Some(click_by),
+            State::Held { long_press_at } => long_press_at,
+        }
+    }
+}
+
+/// Recognises [`Gesture`]s from the state of a button sampled at given instants, debouncing it.
+pub struct GestureDetector {
+    config: GestureConfig,
+    state: State,
+    /// The debounced state of the button.
+    pressed: bool,
+    /// When the button was first seen in the other state, if it has stayed there since.
+    changed_at: Option<Instant>,
+}
+
+impl GestureDetector {
+    pub const fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            state: State::Released,
+            pressed: false,
+            changed_at: None,
+        }
+    }
+
+    pub fn set_config(&mut self, config: GestureConfig) {
+        self.config = config;
+    }
+
+    /// When [`GestureDetector::update`] has to be called next if the button does not change.
+    pub fn deadline(&self) -> Option<Instant> {
+        let timeout = self.state.timeout();
+        match self.changed_at {
+            // A change that started before the timeout takes precedence once it settles.
+            Some(changed_at) if timeout.is_none_or(|timeout| changed_at <= timeout) => {
+                Some(changed_at + self.config.debounce)
+            }
+            _ => timeout,
+        }
+    }
+
+    /// Updates the detector with the button `pressed`, or not, at `now`. Returns a gesture
+    /// recognised by then; call again until `None` as more than one can be due.
+    pub fn update(&mut self, pressed: bool, now: Instant) -> Option<Gesture> {
+        if pressed == self.pressed {
+            // Changes bouncing back within the debounce time are ignored.
+            self.changed_at = None;
+        } else if self.changed_at.is_none() {
+            self.changed_at = Some(now);
+        }
+
+        loop {
+            let timeout = self.state.timeout();
+            let gesture = match self.changed_at {
+                Some(changed_at) if timeout.is_none_or(|timeout| changed_at <= timeout) => {
+                    let settled_at = changed_at + self.config.debounce;
+                    if now < settled_at {
+                        return None;
+                    }
+
+                    self.changed_at = None;
+                    self.pressed = !self.pressed;
+                    self.change(settled_at)
+                }
+                _ => match timeout {
+                    Some(timeout) if timeout <= now => self.time_out(),
+                    _ => return None,
+                },
+            };
+
+            if gesture.is_some() {
+                return gesture;
+            }
+        }
+    }
+
+    /// Moves on from a settled change of the button at `at`.
+    fn change(&mut self, at: Instant) -> Option<Gesture> {
+        match (self.state, self.pressed) {
+            (State::Released, true) => {
+                self.state = State::Pressed {
+                    clicks: 0,
+                    hold_at: at + self.config.hold_time,
+                };
+                None
+            }
+            (State::Pressed { clicks, .. }, false) => {
+                let clicks = clicks + 1;
+                if clicks >= self.config.max_clicks {
+                    self.state = State::Released;
+                    return Some(Gesture::Click(clicks));
+                }
+
+                self.state = State::Between {
+                    clicks,
+                    click_by: at + self.config.click_gap,
+                };
+                None
+            }
+            (State::Between { clicks, .. }, true) => {
+                self.state = State::Pressed {
+                    clicks,
+                    hold_at: at + self.config.hold_time,
+                };
+                None
+            }
+            (State::Held { .. }, false) => {
+                self.state = State::Released;
+                Some(Gesture::Release)
+            }
+            _ => None,
+        }
+    }
+
+    /// Moves on from a state whose timeout has passed.
+    fn time_out(&mut self) -> Option<Gesture> {
+        match self.state {
+            State::Pressed { hold_at, .. } => {
+                // Clicks just before the hold are dropped.
+                self.state = State::Held {
+                    long_press_at: Some(
+                        hold_at - self.config.hold_time + self.config.long_press_time,
+                    ),
+                };
+                Some(Gesture::Hold)
+            }
+            State::Between { clicks, .. } => {
+                self.state = State::Released;
+                Some(Gesture::Click(clicks))
+            }
+            State::Held {
+                long_press_at: Some(_),
+            } => {
+                self.state = State::Held {
+                    long_press_at: None,
+                };
+                Some(Gesture::LongPress)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Recognises [`Gesture`]s on a debounced [`Button`].
+pub struct GestureRecognizer<B: Button> {
+    button: B,
+    detector: GestureDetector,
+}
+
+impl<B: Button> GestureRecognizer<B> {
+    pub fn new(button: B, config: GestureConfig) -> Self {
+        Self {
+            button,
+            detector: GestureDetector::new(config),
+        }
+    }
+
+    pub fn set_config(&mut self, config: GestureConfig) {
+        self.detector.set_config(config);
+    }
+
+    /// Waits for the next gesture.
+    ///
+    /// Dropping the future before it completes keeps the gesture in progress, so this can be
+    /// raced against other events.
+    pub async fn next(&mut self) -> Result<Gesture, B::Error> {
+        loop {
+            let pressed = self.button.is_pressed()?;
+            if let Some(gesture) = self.detector.update(pressed, Instant::now()) {
+                return Ok(gesture);
+            }
+
+            match self.detector.deadline() {
+                Some(deadline) => {
+                    if let Either::First(result) =
+                        select(self.button.wait_for_change(), Timer::at(deadline)).await
+                    {
+                        result?;
+                    }
+                }
+                None => self.button.wait_for_change().await?,
+            }
+        }
+    }
+}
+
+/// Actions a [`Gesture`] can be mapped to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonAction {
+    None,
+    Toggle,
+    On,
+    Off,
+    /// Moves to the given level, switching the light on.
+    Level(u8),
+    /// Starts learning the end stops of the level potentiometer.
+    CalibrateKnob,
+    /// Dims up or down for as long as the button is held, switching the light on. Only applies
+    /// to [`GestureMap::hold`].
+    Dim,
+}
+
+/// The [`ButtonAction`] performed for each [`Gesture`].
+#[derive(Clone, Copy, Debug)]
+pub struct GestureMap {
+    pub click: ButtonAction,
+    pub double_click: ButtonAction,
+    pub triple_click: ButtonAction,
+    pub long_press: ButtonAction,
+    pub hold: ButtonAction,
+}
+
+impl GestureMap {
+    /// The action to perform when `gesture` is recognised.
+    pub fn action(&self, gesture: Gesture) -> ButtonAction {
+        match gesture {
+            Gesture::Click(1) => self.click,
+            Gesture::Click(2) => self.double_click,
+            Gesture::Click(3) => self.triple_click,
+            Gesture::Click(_) => ButtonAction::None,
+            Gesture::Hold => self.hold,
+            Gesture::LongPress => self.long_press,
+            Gesture::Release => ButtonAction::None,
+        }
+    }
+}
+
+impl Default for GestureMap {
+    /// Toggles on click, goes to full brightness on double click, and dims while held, like a
+    /// wall dimmer. A triple click calibrates the potentiometer.
+    fn default() -> Self {
+        Self {
+            click: ButtonAction::Toggle,
+            double_click: ButtonAction::Level(254),
+            triple_click: ButtonAction::CalibrateKnob,
+            long_press: ButtonAction::None,
+            hold: ButtonAction::Dim,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::at;
+
+    fn config() -> GestureConfig {
+        GestureConfig {
+            debounce: Duration::from_millis(5),
+            ..GestureConfig::default()
+        }
+        .with_click_gap(Duration::from_millis(100))
+        .with_hold_time(Duration::from_millis(200))
+        .with_long_press_time(Duration::from_millis(400))
+    }
+
+    /// The gestures recognised, and the milliseconds they were recognised at, on a button
+    /// changing at the given milliseconds. The detector is updated at each change and deadline,
+    /// as [`GestureRecognizer`] does, until all are handled.
+    fn gestures(config: GestureConfig, changes: &[(u64, bool)]) -> Vec<(u64, Gesture)> {
+        let mut detector = GestureDetector::new(config);
+        let mut changes = changes.iter().peekable();
+        let mut pressed = false;
+        let mut recognised = Vec::new();
+
+        loop {
+            let now = match (changes.peek(), detector.deadline()) {
+                (Some((millis, _)), Some(deadline)) => at(*millis).min(deadline),
+                (Some((millis, _)), None) => at(*millis),
+                (None, Some(deadline)) => deadline,
+                (None, None) => return recognised,
+            };
+            while let Some((_, changed)) = changes.next_if(|(millis, _)| at(*millis) <= now) {
+                pressed = *changed;
+            }
+
+            while let Some(gesture) = detector.update(pressed, now) {
+                recognised.push((now.as_millis(), gesture));
+            }
+        }
+    }
+
+    #[test]
+    fn single_click_after_the_click_gap() {
+        let changes = &[(10, true), (50, false)];
+        assert_eq!(gestures(config(), changes), [(155, Gesture::Click(1))]);
+    }
+
+    #[test]
+    fn double_click() {
+        let changes = &[(10, true), (50, false), (90, true), (130, false)];
+        assert_eq!(gestures(config(), changes), [(235, Gesture::Click(2))]);
+    }
+
+    #[test]
+    fn last_click_is_reported_at_once() {
+        let changes = &[(10, true), (50, false)];
+        let config = config().with_max_clicks(1);
+        assert_eq!(gestures(config, changes), [(55, Gesture::Click(1))]);
+    }
+
+    #[test]
+    fn contact_bounce_is_ignored() {
+        let changes = &[
+            (10, true),
+            (11, false),
+            (12, true),
+            (50, false),
+            (51, true),
+            (52, false),
+        ];
+        assert_eq!(gestures(config(), changes), [(157, Gesture::Click(1))]);
+    }
+
+    #[test]
+    fn hold_then_long_press() {
+        let changes = &[(10, true), (600, false)];
+        assert_eq!(
+            gestures(config(), changes),
+            [
+                (215, Gesture::Hold),
+                (415, Gesture::LongPress),
+                (605, Gesture::Release)
+            ]
+        );
+    }
+
+    #[test]
+    fn release_just_before_the_hold_is_a_click() {
+        let changes = &[(10, true), (213, false)];
+        assert_eq!(gestures(config(), changes), [(318, Gesture::Click(1))]);
+    }
+
+    #[test]
+    fn gesture_map_actions() {
+        let map = GestureMap::default();
+
+        assert_eq!(map.action(Gesture::Click(1)), ButtonAction::Toggle);
+        assert_eq!(map.action(Gesture::Click(2)), ButtonAction::Level(254));
+        assert_eq!(map.action(Gesture::Click(4)), ButtonAction::None);
+        assert_eq!(map.action(Gesture::Hold), ButtonAction::Dim);
+        assert_eq!(map.action(Gesture::Release), ButtonAction::None);
+    }
+}
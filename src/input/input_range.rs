@@ -0,0 +1,266 @@
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant};
+
+#[cfg(feature = "defmt")]
+use defmt::{error, info};
+#[cfg(feature = "log")]
+use log::{error, info};
+
+use rs_matter_embassy::matter::error::Error;
+use rs_matter_embassy::stack::persist::KvBlobStore;
+
+use crate::settings::{Decoder, Encoder, Setting, SettingKey, Settings};
+
+/// Time given to turn the input from end to end while calibrating.
+const CALIBRATION_TIME: Duration = Duration::from_secs(10);
+
+/// Smallest span between the end stops that is accepted, as less would make every step of the
+/// input jump several levels.
+const MIN_SPAN: u16 = 256;
+
+/// Signals a [`InputRange`] to be saved by [`persist_input_range`].
+pub type RangeSignal = Signal<CriticalSectionRawMutex, InputRange>;
+
+/// The raw readings at the end stops of an analog input, such as a potentiometer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputRange {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl InputRange {
+    pub const fn new(min: u16, max: u16) -> Self {
+        Self { min, max }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.max >= self.min && self.max - self.min >= MIN_SPAN
+    }
+
+    pub fn span(&self) -> u16 {
+        self.max.saturating_sub(self.min)
+    }
+
+    /// The position of `raw` between the end stops, from `0` to `u16::MAX`.
+    pub fn position(&self, raw: u16) -> u16 {
+        let offset = raw.clamp(self.min, self.max) - self.min;
+        (offset as u32 * u16::MAX as u32 / self.span().max(1) as u32) as u16
+    }
+}
+
+impl Setting for InputRange {
+    const KEY: SettingKey = SettingKey::KnobRange;
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut encoder = Encoder::new(buf);
+        encoder.put(&self.min.to_le_bytes())?;
+        encoder.put(&self.max.to_le_bytes())?;
+        Ok(encoder.written())
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(data);
+        let range = Self::new(
+            u16::from_le_bytes(decoder.take()?),
+            u16::from_le_bytes(decoder.take()?),
+        );
+
+        range.is_valid().then_some(range)
+    }
+}
+
+/// The outcome of a reading passed to [`RangeTracker::observe`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RangeUpdate {
+    Unchanged,
+    /// The end stops were learned by a calibration.
+    Calibrated,
+    /// The input was not turned far enough while calibrating, so the range was kept.
+    CalibrationFailed,
+    /// The end stops drifted far enough from the last reported range to be saved again.
+    Drifted,
+}
+
+/// Learns the [`InputRange`] of an analog input, either in a calibration where the input is
+/// turned from end to end, or gradually from readings past its end stops.
+pub struct RangeTracker {
+    range: InputRange,
+    reported: InputRange,
+    learning: Option<(InputRange, Instant)>,
+}
+
+impl RangeTracker {
+    pub fn new(range: InputRange) -> Self {
+        Self {
+            range,
+            reported: range,
+            learning: None,
+        }
+    }
+
+    pub fn range(&self) -> InputRange {
+        self.range
+    }
+
+    /// Starts learning the end stops from the readings over the next few seconds.
+    pub fn start_calibration(&mut self, now: Instant) {
+        info!("Calibrating input range");
+        self.learning = Some((InputRange::new(u16::MAX, 0), now + CALIBRATION_TIME));
+    }
+
+    pub fn is_calibrating(&self) -> bool {
+        self.learning.is_some()
+    }
+
+    /// Ends a running calibration once its time is up, with the readings observed so far. Called
+    /// by [`RangeTracker::observe`], and on its own when no reading could be taken.
+    pub fn expire(&mut self, now: Instant) -> RangeUpdate {
+        let Some((learned, until)) = self.learning else {
+            return RangeUpdate::Unchanged;
+        };
+        if now < until {
+            return RangeUpdate::Unchanged;
+        }
+
+        self.learning = None;
+        if !learned.is_valid() {
+            error!(
+                "Input range {}..{} too small, keeping the previous one",
+                learned.min, learned.max
+            );
+            return RangeUpdate::CalibrationFailed;
+        }
+
+        info!("Calibrated input range to {}..{}", learned.min, learned.max);
+        self.range = learned;
+        self.reported = learned;
+        RangeUpdate::Calibrated
+    }
+
+    /// Updates the range from a raw reading taken at `now`.
+    pub fn observe(&mut self, raw: u16, now: Instant) -> RangeUpdate {
+        if let Some((mut learned, until)) = self.learning {
+            learned.min = learned.min.min(raw);
+            learned.max = learned.max.max(raw);
+
+            self.learning = Some((learned, until));
+            return self.expire(now);
+        }
+
+        // Readings past an end stop move it out, so the range follows stops drifting with
+        // temperature and wear. Stops drifting in cannot be told apart from the input resting
+        // close to them, so those are only picked up by calibrating again.
+        self.range.min = self.range.min.min(raw);
+        self.range.max = self.range.max.max(raw);
+
+        let threshold = self.reported.span() / 64;
+        if self.range.min.abs_diff(self.reported.min) > threshold
+            || self.range.max.abs_diff(self.reported.max) > threshold
+        {
+            self.reported = self.range;
+            return RangeUpdate::Drifted;
+        }
+
+        RangeUpdate::Unchanged
+    }
+}
+
+/// Saves each range signalled through `updates`.
+pub async fn persist_input_range<S: KvBlobStore>(
+    settings: &Settings<S>,
+    updates: &RangeSignal,
+) -> ! {
+    loop {
+        let range = updates.wait().await;
+
+        match settings.save(&range).await {
+            Ok(()) => info!("Saved input range {}..{}", range.min, range.max),
+            Err(e) => error!("Failed to save input range: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::at;
+
+    #[test]
+    fn position_scales_between_the_end_stops() {
+        let range = InputRange::new(1000, 3000);
+
+        assert_eq!(range.position(500), 0);
+        assert_eq!(range.position(2000), u16::MAX / 2);
+        assert_eq!(range.position(3500), u16::MAX);
+    }
+
+    #[test]
+    fn narrow_ranges_are_invalid() {
+        assert!(!InputRange::new(0, MIN_SPAN - 1).is_valid());
+        assert!(InputRange::new(0, MIN_SPAN).is_valid());
+        assert!(!InputRange::new(10, 5).is_valid());
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let range = InputRange::new(120, 3980);
+        let mut buf = [0; 8];
+        let len = range.encode(&mut buf).unwrap();
+
+        assert_eq!(InputRange::decode(&buf[..len]), Some(range));
+        assert_eq!(InputRange::decode(&buf[..len - 1]), None);
+    }
+
+    #[test]
+    fn calibration_learns_the_end_stops() {
+        let mut tracker = RangeTracker::new(InputRange::new(0, 4095));
+        tracker.start_calibration(at(0));
+
+        assert_eq!(tracker.observe(1000, at(1000)), RangeUpdate::Unchanged);
+        assert_eq!(tracker.observe(3000, at(2000)), RangeUpdate::Unchanged);
+        assert!(tracker.is_calibrating());
+
+        assert_eq!(tracker.observe(2000, at(10_000)), RangeUpdate::Calibrated);
+        assert_eq!(tracker.range(), InputRange::new(1000, 3000));
+        assert!(!tracker.is_calibrating());
+    }
+
+    #[test]
+    fn short_calibration_keeps_the_range() {
+        let range = InputRange::new(0, 4095);
+        let mut tracker = RangeTracker::new(range);
+        tracker.start_calibration(at(0));
+        tracker.observe(1000, at(1000));
+
+        assert_eq!(
+            tracker.observe(1100, at(10_000)),
+            RangeUpdate::CalibrationFailed
+        );
+        assert_eq!(tracker.range(), range);
+    }
+
+    #[test]
+    fn calibration_ends_on_time_without_readings() {
+        let mut tracker = RangeTracker::new(InputRange::new(0, 4095));
+        tracker.start_calibration(at(0));
+        tracker.observe(1000, at(1000));
+        tracker.observe(3000, at(2000));
+
+        assert_eq!(tracker.expire(at(9_999)), RangeUpdate::Unchanged);
+        assert_eq!(tracker.expire(at(10_000)), RangeUpdate::Calibrated);
+        assert_eq!(tracker.range(), InputRange::new(1000, 3000));
+        assert_eq!(tracker.expire(at(20_000)), RangeUpdate::Unchanged);
+    }
+
+    #[test]
+    fn end_stops_drift_outwards() {
+        let mut tracker = RangeTracker::new(InputRange::new(1000, 3000));
+
+        assert_eq!(tracker.observe(990, at(0)), RangeUpdate::Unchanged);
+        assert_eq!(tracker.range(), InputRange::new(990, 3000));
+        assert_eq!(tracker.observe(960, at(20)), RangeUpdate::Drifted);
+        assert_eq!(tracker.observe(2000, at(40)), RangeUpdate::Unchanged);
+        assert_eq!(tracker.range(), InputRange::new(960, 3000));
+    }
+}
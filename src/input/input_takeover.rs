@@ -0,0 +1,99 @@
+#[cfg(feature = "defmt")]
+use defmt::debug;
+#[cfg(feature = "log")]
+use log::debug;
+
+/// Soft takeover for an absolute input such as a potentiometer, whose position no longer
+/// matches the level once it was changed by other means.
+///
+/// Once [released](SoftTakeover::release), the input is ignored until it is moved past the
+/// current level, or by more than the threshold, so that jitter or a small nudge does not jump
+/// the level back to the input's position.
+#[derive(Clone, Copy, Debug)]
+pub struct SoftTakeover {
+    threshold: Option<u8>,
+    // The input position when released, or `None` while the input controls the level
+    released_at: Option<u8>,
+}
+
+impl SoftTakeover {
+    /// Creates a takeover picking up the level when the input moves past it, or by `threshold`
+    /// levels if given.
+    pub const fn new(threshold: Option<u8>) -> Self {
+        Self {
+            threshold,
+            released_at: None,
+        }
+    }
+
+    /// Releases the level after it was changed by other means, with the input at `position`.
+    pub fn release(&mut self, position: u8) {
+        if self.released_at.is_none() {
+            debug!("Input released at {}", position);
+        }
+        self.released_at = Some(position);
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.released_at.is_none()
+    }
+
+    /// Returns whether the input at `position` controls the `current` level, picking the
+    /// level up if the input has moved past it or beyond the threshold.
+    pub fn update(&mut self, position: u8, current: u8) -> bool {
+        let Some(released_at) = self.released_at else {
+            return true;
+        };
+
+        let crossed = position == current || (released_at < current) != (position < current);
+        let moved = self
+            .threshold
+            .is_some_and(|threshold| position.abs_diff(released_at) >= threshold);
+
+        if crossed || moved {
+            debug!("Input picked up the level at {}", position);
+            self.released_at = None;
+        }
+
+        self.released_at.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engaged_until_released() {
+        let mut takeover = SoftTakeover::new(None);
+        assert!(takeover.is_engaged());
+        assert!(takeover.update(10, 200));
+
+        takeover.release(10);
+        assert!(!takeover.is_engaged());
+        assert!(!takeover.update(12, 200));
+    }
+
+    #[test]
+    fn picks_up_when_crossing_the_level() {
+        let mut takeover = SoftTakeover::new(None);
+        takeover.release(50);
+
+        assert!(!takeover.update(99, 100));
+        assert!(takeover.update(101, 100));
+        assert!(takeover.is_engaged());
+
+        takeover.release(150);
+        assert!(takeover.update(100, 100));
+    }
+
+    #[test]
+    fn picks_up_beyond_the_threshold() {
+        let mut takeover = SoftTakeover::new(Some(20));
+        takeover.release(50);
+
+        assert!(!takeover.update(40, 200));
+        assert!(!takeover.update(69, 200));
+        assert!(takeover.update(70, 200));
+    }
+}
@@ -0,0 +1,97 @@
+use embassy_time::{Duration, Instant};
+
+/// Coalesces a stream of values into updates at most once per interval. The latest value is
+/// always passed on, so the last update carries the value the input settled at.
+#[derive(Clone, Copy, Debug)]
+pub struct Throttle<T> {
+    interval: Duration,
+    pending: Option<T>,
+    sent_at: Option<Instant>,
+}
+
+impl<T: Copy> Throttle<T> {
+    pub const fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            pending: None,
+            sent_at: None,
+        }
+    }
+
+    /// Replaces the value waiting to be passed on.
+    pub fn push(&mut self, value: T) {
+        self.pending = Some(value);
+    }
+
+    /// Drops the value waiting to be passed on, e.g. because it was sent by other means.
+    pub fn clear(&mut self) {
+        self.pending = None;
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// When the waiting value is due, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending?;
+        Some(
+            self.sent_at
+                .map_or(Instant::MIN, |sent_at| sent_at + self.interval),
+        )
+    }
+
+    /// Takes the waiting value if it is due at `now`.
+    pub fn poll(&mut self, now: Instant) -> Option<T> {
+        if self.deadline()? > now {
+            return None;
+        }
+
+        self.sent_at = Some(now);
+        self.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::at;
+
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn first_value_is_due_at_once() {
+        let mut throttle = Throttle::new(INTERVAL);
+        assert_eq!(throttle.deadline(), None);
+
+        throttle.push(1);
+        assert_eq!(throttle.deadline(), Some(Instant::MIN));
+        assert_eq!(throttle.poll(at(5)), Some(1));
+        assert!(!throttle.is_pending());
+    }
+
+    #[test]
+    fn coalesces_values_within_the_interval() {
+        let start = at(1000);
+        let mut throttle = Throttle::new(INTERVAL);
+        throttle.push(1);
+        assert_eq!(throttle.poll(start), Some(1));
+
+        throttle.push(2);
+        throttle.push(3);
+        assert_eq!(throttle.deadline(), Some(start + INTERVAL));
+        assert_eq!(throttle.poll(start + Duration::from_millis(50)), None);
+        assert_eq!(throttle.poll(start + INTERVAL), Some(3));
+        assert_eq!(throttle.poll(start + INTERVAL * 3), None);
+    }
+
+    #[test]
+    fn clear_drops_the_pending_value() {
+        let mut throttle = Throttle::new(INTERVAL);
+        throttle.push(1);
+        throttle.clear();
+
+        assert!(!throttle.is_pending());
+        assert_eq!(throttle.poll(at(5)), None);
+    }
+}
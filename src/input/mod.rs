@@ -0,0 +1,8 @@
+pub mod input_analog;
+pub mod input_button;
+pub mod input_encoder;
+pub mod input_filter;
+pub mod input_gesture;
+pub mod input_range;
+pub mod input_takeover;
+pub mod input_throttle;
@@ -0,0 +1,160 @@
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, error, info};
+#[cfg(feature = "log")]
+use log::{debug, error, info};
+
+use rs_matter_embassy::matter::dm::clusters::on_off::StartUpOnOffEnum;
+use rs_matter_embassy::matter::error::Error;
+use rs_matter_embassy::stack::persist::KvBlobStore;
+
+use crate::led::led_driver::DEFAULT_LEVEL;
+use crate::settings::{Decoder, Encoder, Setting, SettingKey, Settings};
+
+/// Time without further changes after which the attributes are saved, so that turning the knob
+/// or a level transition does not wear out the flash.
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+/// Signals the [`LightAttributes`] to be saved by [`persist_light_attributes`].
+pub type AttributesSignal = Signal<CriticalSectionRawMutex, LightAttributes>;
+
+/// The non-volatile attributes of the OnOff and LevelControl clusters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightAttributes {
+    pub on_off: bool,
+    /// The OnOff state at power-on, or `None` for the previous one.
+    pub start_up_on_off: Option<StartUpOnOffEnum>,
+    pub current_level: Option<u8>,
+    /// The level at power-on: `Some(0)` for the minimum, or `None` for the previous one.
+    pub start_up_current_level: Option<u8>,
+}
+
+impl LightAttributes {
+    /// The attributes of a lamp that was never configured, or reset: on at the LED driver's
+    /// default level.
+    pub const DEFAULT: Self = Self {
+        on_off: true,
+        start_up_on_off: None,
+        current_level: Some(DEFAULT_LEVEL),
+        start_up_current_level: None,
+    };
+}
+
+impl Default for LightAttributes {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Setting for LightAttributes {
+    const KEY: SettingKey = SettingKey::LightAttributes;
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let start_up_on_off = match self.start_up_on_off {
+            Some(StartUpOnOffEnum::Off) => 0,
+            Some(StartUpOnOffEnum::On) => 1,
+            Some(StartUpOnOffEnum::Toggle) => 2,
+            None => u8::MAX,
+        };
+
+        let mut encoder = Encoder::new(buf);
+        encoder.put(&[self.on_off as u8, start_up_on_off])?;
+        put_level(&mut encoder, self.current_level)?;
+        put_level(&mut encoder, self.start_up_current_level)?;
+        Ok(encoder.written())
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(data);
+        let [on_off, start_up_on_off] = decoder.take()?;
+        let start_up_on_off = match start_up_on_off {
+            0 => Some(StartUpOnOffEnum::Off),
+            1 => Some(StartUpOnOffEnum::On),
+            2 => Some(StartUpOnOffEnum::Toggle),
+            u8::MAX => None,
+            _ => return None,
+        };
+
+        Some(Self {
+            on_off: on_off != 0,
+            start_up_on_off,
+            current_level: take_level(&mut decoder)?,
+            start_up_current_level: take_level(&mut decoder)?,
+        })
+    }
+}
+
+/// Writes a nullable level as a presence flag followed by the level.
+fn put_level(encoder: &mut Encoder<'_>, level: Option<u8>) -> Result<(), Error> {
+    encoder.put(&[level.is_some() as u8, level.unwrap_or(0)])
+}
+
+fn take_level(decoder: &mut Decoder<'_>) -> Option<Option<u8>> {
+    let [present, level] = decoder.take()?;
+    Some((present != 0).then_some(level))
+}
+
+/// Saves the attributes signalled through `updates` once they stop changing, skipping those
+/// matching the ones in the store. The store is read each time, so a setting removed by a
+/// factory reset is not mistaken for the attributes saved before.
+pub async fn persist_light_attributes<S: KvBlobStore>(
+    settings: &Settings<S>,
+    updates: &AttributesSignal,
+) -> ! {
+    loop {
+        let mut attributes = updates.wait().await;
+        while let Either::First(newer) = select(updates.wait(), Timer::after(SAVE_DELAY)).await {
+            attributes = newer;
+        }
+
+        let saved = settings.load::<LightAttributes>().await.unwrap_or_default();
+        if attributes == saved {
+            debug!("Light attributes unchanged");
+            continue;
+        }
+
+        match settings.save(&attributes).await {
+            Ok(()) => {
+                info!("Saved light attributes");
+            }
+            Err(e) => error!("Failed to save light attributes: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(attributes: LightAttributes) -> Option<LightAttributes> {
+        let mut buf = [0; 8];
+        let len = attributes.encode(&mut buf).unwrap();
+        LightAttributes::decode(&buf[..len])
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        assert_eq!(
+            round_trip(LightAttributes::DEFAULT),
+            Some(LightAttributes::DEFAULT)
+        );
+
+        let attributes = LightAttributes {
+            on_off: false,
+            start_up_on_off: Some(StartUpOnOffEnum::Toggle),
+            current_level: None,
+            start_up_current_level: Some(0),
+        };
+        assert_eq!(round_trip(attributes), Some(attributes));
+    }
+
+    #[test]
+    fn decode_rejects_invalid_data() {
+        assert_eq!(LightAttributes::decode(&[1, 3, 0, 0, 0, 0]), None);
+        assert_eq!(LightAttributes::decode(&[1, 0, 1, 254, 0]), None);
+    }
+}
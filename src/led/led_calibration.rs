@@ -0,0 +1,288 @@
+#[cfg(feature = "defmt")]
+use defmt::{error, info};
+#[cfg(feature = "log")]
+use log::{error, info};
+
+use rs_matter_embassy::matter::error::Error;
+use rs_matter_embassy::stack::persist::KvBlobStore;
+
+use crate::led::led_colour::Rgb16;
+use crate::led::led_driver::LedFeedback;
+use crate::settings::{Decoder, Encoder, Setting, SettingKey, Settings};
+
+/// Per-device correction of the LED channels, compensating for differences between LED batches
+/// and the white point of the diffuser.
+///
+/// Colours are decoded to linear light with the per-channel `gamma`, mixed through `matrix` and
+/// finally scaled by the per-channel `gain`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    pub gamma: [f32; 3],
+    /// Rows produce the red, green and blue drive values from the linear red, green and blue.
+    pub matrix: [[f32; 3]; 3],
+    pub gain: [f32; 3],
+}
+
+impl Default for Calibration {
+    /// No correction beyond the gamma of 2.8 assumed for WS2812 pixels.
+    fn default() -> Self {
+        Self {
+            gamma: [2.8; 3],
+            matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            gain: [1.0; 3],
+        }
+    }
+}
+
+impl Setting for Calibration {
+    const KEY: SettingKey = SettingKey::Calibration;
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut encoder = Encoder::new(buf);
+        for value in self
+            .gamma
+            .iter()
+            .chain(self.matrix.iter().flatten())
+            .chain(self.gain.iter())
+        {
+            encoder.put_f32(*value)?;
+        }
+        Ok(encoder.written())
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(data);
+        let mut triple = || -> Option<[f32; 3]> {
+            Some([
+                decoder.take_f32()?,
+                decoder.take_f32()?,
+                decoder.take_f32()?,
+            ])
+        };
+
+        let calibration = Self {
+            gamma: triple()?,
+            matrix: [triple()?, triple()?, triple()?],
+            gain: triple()?,
+        };
+
+        calibration.is_valid().then_some(calibration)
+    }
+}
+
+impl Calibration {
+    /// Whether every factor is usable. Negative or non-finite values would produce garbage output.
+    pub fn is_valid(&self) -> bool {
+        let usable = |value: &f32| value.is_finite() && *value >= 0.0;
+
+        self.gamma.iter().all(|gamma| usable(gamma) && *gamma > 0.0)
+            && self.matrix.iter().flatten().all(usable)
+            && self.gain.iter().all(usable)
+    }
+}
+
+/// A [`Calibration`] prepared for applying to every frame.
+pub struct Correction {
+    calibration: Calibration,
+    /// Linear light of each channel at 257 evenly spaced points of its 16-bit input, with both
+    /// end points included so that any input falls between two entries.
+    curves: [[u16; 257]; 3],
+    /// The calibration matrix with the gain folded into its rows.
+    matrix: [[f32; 3]; 3],
+}
+
+impl Correction {
+    pub fn new(calibration: Calibration) -> Self {
+        let mut curves = [[0; 257]; 3];
+        for (curve, gamma) in curves.iter_mut().zip(calibration.gamma) {
+            for (input, value) in curve.iter_mut().enumerate() {
+                let linear = libm::powf(input as f32 / 256.0, gamma);
+                *value = (linear * u16::MAX as f32 + 0.5) as u16;
+            }
+        }
+
+        let mut matrix = calibration.matrix;
+        for (row, gain) in matrix.iter_mut().zip(calibration.gain) {
+            row.iter_mut().for_each(|factor| *factor *= gain);
+        }
+
+        Self {
+            calibration,
+            curves,
+            matrix,
+        }
+    }
+
+    pub fn calibration(&self) -> Calibration {
+        self.calibration
+    }
+
+    /// Converts `colour` to the linear drive values of the LED channels.
+    pub fn apply(&self, colour: Rgb16) -> Rgb16 {
+        let linear = [
+            Self::decode(&self.curves[0], colour.r),
+            Self::decode(&self.curves[1], colour.g),
+            Self::decode(&self.curves[2], colour.b),
+        ];
+
+        let channel = |row: [f32; 3]| {
+            let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+            (value + 0.5).clamp(0.0, u16::MAX as f32) as u16
+        };
+
+        Rgb16::new(
+            channel(self.matrix[0]),
+            channel(self.matrix[1]),
+            channel(self.matrix[2]),
+        )
+    }
+
+    /// Looks up `input` on `curve`, interpolating between its entries.
+    fn decode(curve: &[u16; 257], input: u16) -> f32 {
+        let position = input as f32 * 256.0 / u16::MAX as f32;
+        let index = (position as usize).min(255);
+        let fraction = position - index as f32;
+        let (low, high) = (curve[index] as f32, curve[index + 1] as f32);
+
+        low + (high - low) * fraction
+    }
+}
+
+impl Default for Correction {
+    fn default() -> Self {
+        Self::new(Calibration::default())
+    }
+}
+
+/// Saves the calibration applied by the driver whenever it differs from the `saved` one.
+pub async fn persist_calibration<S: KvBlobStore>(
+    settings: &Settings<S>,
+    feedback: &LedFeedback,
+    mut saved: Calibration,
+) -> ! {
+    let mut receiver = feedback
+        .receiver()
+        .expect("More LED feedback consumers than LED_FEEDBACK_RECEIVERS");
+
+    loop {
+        let calibration = receiver.changed().await.calibration;
+        if calibration == saved {
+            continue;
+        }
+
+        match settings.save(&calibration).await {
+            Ok(()) => {
+                info!("Saved LED calibration");
+                saved = calibration;
+            }
+            Err(e) => error!("Failed to save LED calibration: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A calibration with a linear response, so that drive values follow the matrix exactly.
+    fn linear() -> Calibration {
+        Calibration {
+            gamma: [1.0; 3],
+            ..Calibration::default()
+        }
+    }
+
+    /// Checks `actual` against `expected`, allowing for the rounding of the lookup table.
+    fn assert_near(actual: u16, expected: u16) {
+        assert!(actual.abs_diff(expected) <= 1, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn rejects_unusable_factors() {
+        assert!(Calibration::default().is_valid());
+
+        let mut calibration = Calibration::default();
+        calibration.gamma[1] = 0.0;
+        assert!(!calibration.is_valid());
+
+        let mut calibration = Calibration::default();
+        calibration.matrix[2][0] = -0.1;
+        assert!(!calibration.is_valid());
+
+        let mut calibration = Calibration::default();
+        calibration.gain[0] = f32::NAN;
+        assert!(!calibration.is_valid());
+
+        let mut calibration = Calibration::default();
+        calibration.gain[2] = f32::INFINITY;
+        assert!(!calibration.is_valid());
+    }
+
+    #[test]
+    fn invalid_settings_are_not_decoded() {
+        let mut buf = [0; 64];
+        let mut calibration = linear();
+        calibration.matrix[0][1] = 0.25;
+        let len = calibration.encode(&mut buf).unwrap();
+        assert_eq!(Calibration::decode(&buf[..len]), Some(calibration));
+
+        calibration.gamma[0] = -1.0;
+        let len = calibration.encode(&mut buf).unwrap();
+        assert_eq!(Calibration::decode(&buf[..len]), None);
+        assert_eq!(Calibration::decode(&buf[..len - 1]), None);
+    }
+
+    #[test]
+    fn lookup_table_covers_the_full_range() {
+        let correction = Correction::default();
+        assert_eq!(correction.apply(Rgb16::new(0, 0, 0)), Rgb16::new(0, 0, 0));
+        assert_eq!(
+            correction.apply(Rgb16::new(u16::MAX, u16::MAX, u16::MAX)),
+            Rgb16::new(u16::MAX, u16::MAX, u16::MAX)
+        );
+
+        // Between the table entries the curve is interpolated, and never decreases.
+        let mut previous = 0;
+        for input in (0..=u16::MAX).step_by(97) {
+            let output = correction.apply(Rgb16::new(input, 0, 0)).r;
+            assert!(output >= previous, "{output} < {previous} at {input}");
+            previous = output;
+        }
+    }
+
+    #[test]
+    fn applies_the_gamma_per_channel() {
+        let mut calibration = linear();
+        calibration.gamma[2] = 2.0;
+        let correction = Correction::new(calibration);
+
+        let half = 0x8000;
+        let output = correction.apply(Rgb16::new(half, half, half));
+        assert_near(output.r, half);
+        assert_near(output.g, half);
+        assert_near(output.b, 0x4000);
+    }
+
+    #[test]
+    fn mixes_through_the_matrix_then_applies_the_gain() {
+        let mut calibration = linear();
+        // Red also drives a little green, and the green channel runs at half gain.
+        calibration.matrix[1] = [0.25, 1.0, 0.0];
+        calibration.gain[1] = 0.5;
+        let correction = Correction::new(calibration);
+
+        let output = correction.apply(Rgb16::new(0x8000, 0x4000, 0));
+        assert_near(output.r, 0x8000);
+        assert_near(output.g, 0x1000 + 0x2000);
+        assert_eq!(output.b, 0);
+    }
+
+    #[test]
+    fn drive_values_saturate() {
+        let mut calibration = linear();
+        calibration.gain[0] = 2.0;
+        let correction = Correction::new(calibration);
+
+        assert_eq!(correction.apply(Rgb16::new(0xc000, 0, 0)).r, u16::MAX);
+    }
+}
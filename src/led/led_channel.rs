@@ -0,0 +1,281 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+use crate::led::led_compositor::Overlay;
+use crate::led::led_driver::ControlMessage;
+use crate::led::led_indication::Indication;
+use crate::reset::Resettable;
+
+/// The commands waiting to be picked up by the driver, at most one per property.
+#[derive(Clone, Copy, Default)]
+struct Pending {
+    reset: Option<ControlMessage>,
+    mode: Option<ControlMessage>,
+    on: Option<ControlMessage>,
+    level: Option<ControlMessage>,
+    colour: Option<ControlMessage>,
+    calibration: Option<ControlMessage>,
+    curve: Option<ControlMessage>,
+    frame_rate: Option<ControlMessage>,
+    indications: [Option<ControlMessage>; Overlay::COUNT],
+}
+
+impl Pending {
+    fn slot(&mut self, message: &ControlMessage) -> &mut Option<ControlMessage> {
+        match message {
+            ControlMessage::Reset => &mut self.reset,
+            ControlMessage::SetMode(_) => &mut self.mode,
+            ControlMessage::SetOn { .. } | ControlMessage::OffWithEffect(_) => &mut self.on,
+            ControlMessage::SetBrightness { .. } => &mut self.level,
+            ControlMessage::SetColour { .. } => &mut self.colour,
+            ControlMessage::SetCalibration(_) => &mut self.calibration,
+            ControlMessage::SetBrightnessCurve(_) => &mut self.curve,
+            ControlMessage::SetFrameRate(_) => &mut self.frame_rate,
+            ControlMessage::Indicate { overlay, .. }
+            | ControlMessage::FinishIndication(overlay) => &mut self.indications[*overlay as usize],
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.reset.is_none()
+            && self.mode.is_none()
+            && self.on.is_none()
+            && self.level.is_none()
+            && self.colour.is_none()
+            && self.calibration.is_none()
+            && self.curve.is_none()
+            && self.frame_rate.is_none()
+            && self.indications.iter().all(Option::is_none)
+    }
+
+    /// Takes the next command. A pending reset always goes first, as sending it clears every
+    /// property command sent before it, and indications follow the properties.
+    fn take(&mut self) -> Option<ControlMessage> {
+        self.reset
+            .take()
+            .or_else(|| self.mode.take())
+            .or_else(|| self.on.take())
+            .or_else(|| self.level.take())
+            .or_else(|| self.colour.take())
+            .or_else(|| self.calibration.take())
+            .or_else(|| self.curve.take())
+            .or_else(|| self.frame_rate.take())
+            .or_else(|| self.indications.iter_mut().rev().find_map(Option::take))
+    }
+}
+
+/// A latest-state channel between the handlers and the LED driver.
+///
+/// Each property holds only the most recently sent command, so bursts of updates are coalesced
+/// rather than queued. Sending never blocks or fails, and the final requested state of every
+/// property always reaches the driver.
+pub struct LedChannel {
+    pending: Mutex<CriticalSectionRawMutex, Cell<Pending>>,
+    wakeup: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl LedChannel {
+    pub const fn new() -> Self {
+        Self {
+            pending: Mutex::new(Cell::new(Pending {
+                reset: None,
+                mode: None,
+                on: None,
+                level: None,
+                colour: None,
+                calibration: None,
+                curve: None,
+                frame_rate: None,
+                indications: [None; Overlay::COUNT],
+            })),
+            wakeup: Signal::new(),
+        }
+    }
+
+    pub fn sender(&self) -> LedSender<'_> {
+        LedSender { channel: self }
+    }
+
+    pub fn receiver(&self) -> LedReceiver<'_> {
+        LedReceiver { channel: self }
+    }
+}
+
+impl Default for LedChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct LedSender<'a> {
+    channel: &'a LedChannel,
+}
+
+impl LedSender<'_> {
+    /// Sends `message` to the driver, replacing any pending command for the same property.
+    ///
+    /// A [`ControlMessage::Reset`] also drops the pending property commands, but keeps pending
+    /// indications, so that e.g. the confirmation of a factory reset survives the reset itself.
+    pub fn send(&self, message: ControlMessage) {
+        self.channel.pending.lock(|pending| {
+            let mut value = pending.get();
+            if message == ControlMessage::Reset {
+                // Property commands sent before a reset are superseded by it.
+                value = Pending {
+                    indications: value.indications,
+                    ..Pending::default()
+                };
+            }
+            *value.slot(&message) = Some(message);
+            pending.set(value);
+        });
+        self.channel.wakeup.signal(());
+    }
+
+    /// Shows `indication` as a notification above the light. The light, including any running
+    /// effect, shows through again once the indication finishes.
+    pub fn notify(&self, indication: Indication) {
+        self.send(ControlMessage::Indicate {
+            overlay: Overlay::Notification,
+            indication: Some(indication),
+        });
+    }
+
+    /// Whether the driver has picked up every command sent so far.
+    pub fn is_idle(&self) -> bool {
+        self.channel
+            .pending
+            .lock(|pending| pending.get().is_empty())
+    }
+}
+
+pub struct LedReceiver<'a> {
+    channel: &'a LedChannel,
+}
+
+impl LedReceiver<'_> {
+    /// Waits for the next pending command.
+    pub async fn receive(&self) -> ControlMessage {
+        loop {
+            let message = self.channel.pending.lock(|pending| {
+                let mut value = pending.get();
+                let message = value.take();
+                pending.set(value);
+                message
+            });
+
+            if let Some(message) = message {
+                return message;
+            }
+
+            self.channel.wakeup.wait().await;
+        }
+    }
+}
+
+impl Resettable for LedSender<'_> {
+    /// Returns the driver to its factory default state, see [`ControlMessage::Reset`].
+    fn reset(&self) {
+        self.send(ControlMessage::Reset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_time::Duration;
+
+    use super::*;
+    use crate::led::led_driver::Mode;
+    use crate::test_util::drain;
+
+    const INSTANT: Duration = Duration::from_ticks(0);
+
+    fn level(level: u8) -> ControlMessage {
+        ControlMessage::SetBrightness {
+            level,
+            transition: INSTANT,
+        }
+    }
+
+    fn on(on: bool) -> ControlMessage {
+        ControlMessage::SetOn {
+            on,
+            transition: INSTANT,
+        }
+    }
+
+    fn indicate(overlay: Overlay, indication: Indication) -> ControlMessage {
+        ControlMessage::Indicate {
+            overlay,
+            indication: Some(indication),
+        }
+    }
+
+    #[test]
+    fn latest_value_wins_per_property() {
+        let channel = LedChannel::new();
+        let sender = channel.sender();
+        sender.send(level(10));
+        sender.send(on(false));
+        sender.send(level(20));
+        sender.send(on(true));
+        sender.send(level(30));
+
+        assert_eq!(drain(&channel), [on(true), level(30)]);
+        assert!(channel.sender().is_idle());
+    }
+
+    #[test]
+    fn latest_indication_wins_per_overlay() {
+        let channel = LedChannel::new();
+        let sender = channel.sender();
+        sender.notify(Indication::Commissioned);
+        sender.send(indicate(Overlay::Status, Indication::NetworkLost));
+        sender.send(indicate(Overlay::Status, Indication::Commissioning));
+        sender.notify(Indication::ResetConfirmed);
+
+        // Higher overlays go first.
+        assert_eq!(
+            drain(&channel),
+            [
+                indicate(Overlay::Status, Indication::Commissioning),
+                indicate(Overlay::Notification, Indication::ResetConfirmed),
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_drops_earlier_properties_and_goes_first() {
+        let channel = LedChannel::new();
+        let sender = channel.sender();
+        sender.send(level(10));
+        sender.send(ControlMessage::SetMode(Mode::ColourChanging {
+            duration: Duration::from_secs(10),
+        }));
+        sender.send(ControlMessage::Reset);
+        sender.send(on(false));
+
+        assert_eq!(drain(&channel), [ControlMessage::Reset, on(false)]);
+    }
+
+    #[test]
+    fn reset_keeps_pending_indications() {
+        let channel = LedChannel::new();
+        let sender = channel.sender();
+        sender.send(level(10));
+        sender.send(indicate(Overlay::Maintenance, Indication::ResetConfirmed));
+        sender.send(ControlMessage::Reset);
+
+        assert_eq!(
+            drain(&channel),
+            [
+                ControlMessage::Reset,
+                indicate(Overlay::Maintenance, Indication::ResetConfirmed),
+            ]
+        );
+    }
+}
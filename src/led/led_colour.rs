@@ -0,0 +1,120 @@
+use palette::white_point::D65;
+use palette::{FromColor, Hsv, Srgb, Yxy};
+use smart_leds::RGB8;
+
+/// A colour with 16 bits per channel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rgb16 {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+}
+
+impl Rgb16 {
+    pub const fn new(r: u16, g: u16, b: u16) -> Self {
+        Self { r, g, b }
+    }
+
+    fn from_f32(r: f32, g: f32, b: f32) -> Self {
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * u16::MAX as f32 + 0.5) as u16;
+        Self::new(channel(r), channel(g), channel(b))
+    }
+}
+
+impl From<RGB8> for Rgb16 {
+    fn from(colour: RGB8) -> Self {
+        // 257 maps 0..=255 exactly onto 0..=65535.
+        Self::new(
+            colour.r as u16 * 257,
+            colour.g as u16 * 257,
+            colour.b as u16 * 257,
+        )
+    }
+}
+
+impl From<Rgb16> for RGB8 {
+    fn from(colour: Rgb16) -> Self {
+        let channel = |value: u16| ((value as u32 * 255 + 0x7fff) / u16::MAX as u32) as u8;
+        RGB8 {
+            r: channel(colour.r),
+            g: channel(colour.g),
+            b: channel(colour.b),
+        }
+    }
+}
+
+/// Converts Matter `CurrentX`/`CurrentY` coordinates to sRGB.
+pub fn xy_to_rgb(x: u16, y: u16) -> Rgb16 {
+    let x_f32 = x as f32 / 65536.0;
+    let y_f32 = y as f32 / 65536.0;
+
+    let yxy: Yxy<D65, f32> = Yxy::new(x_f32, y_f32, 1.0);
+
+    let srgb: Srgb<f32> = Srgb::from_color(yxy);
+
+    Rgb16::from_f32(srgb.red, srgb.green, srgb.blue)
+}
+
+/// The hue of the Matter `CurrentX`/`CurrentY` coordinates, in degrees.
+pub fn xy_to_hue(x: u16, y: u16) -> f32 {
+    let yxy: Yxy<D65, f32> = Yxy::new(x as f32 / 65536.0, y as f32 / 65536.0, 1.0);
+    let srgb: Srgb<f32> = Srgb::from_color(yxy);
+
+    // Clip colours outside of the sRGB gamut, which only shifts their hue slightly.
+    let clip = |value: f32| value.clamp(0.0, 1.0);
+    let hsv: Hsv = Hsv::from_color(Srgb::new(clip(srgb.red), clip(srgb.green), clip(srgb.blue)));
+
+    hsv.hue.into_positive_degrees()
+}
+
+/// The Matter `CurrentX`/`CurrentY` coordinates of the fully saturated colour of the given hue,
+/// in degrees.
+pub fn hue_to_xy(hue: f32) -> (u16, u16) {
+    let hsv: Hsv = Hsv::new(hue, 1.0, 1.0);
+    let srgb: Srgb<f32> = Srgb::from_color(hsv);
+    let yxy: Yxy<D65, f32> = Yxy::from_color(srgb);
+
+    // Matter limits the coordinates to 0xFEFF.
+    let coordinate = |value: f32| (value * 65536.0).clamp(0.0, 65279.0) as u16;
+    (coordinate(yxy.x), coordinate(yxy.y))
+}
+
+/// Converts a colour temperature in mireds to sRGB, scaled so that the brightest channel is at
+/// full intensity.
+pub fn mireds_to_rgb(mireds: u16) -> Rgb16 {
+    let (x, y) = mireds_to_xy(mireds);
+
+    let yxy: Yxy<D65, f32> = Yxy::new(x, y, 1.0);
+    let srgb: Srgb<f32> = Srgb::from_color(yxy);
+
+    // Warm whites lie outside of the sRGB gamut at full luminance, so normalise rather than clip.
+    let max = srgb.red.max(srgb.green).max(srgb.blue).max(f32::EPSILON);
+
+    Rgb16::from_f32(srgb.red / max, srgb.green / max, srgb.blue / max)
+}
+
+/// Approximates the CIE 1931 chromaticity of a black body at the given colour temperature,
+/// using the cubic spline of Kim et al. Valid from 1667 K to 25000 K.
+#[allow(clippy::excessive_precision)] // The coefficients as published.
+fn mireds_to_xy(mireds: u16) -> (f32, f32) {
+    let kelvin = (1_000_000.0 / mireds.max(1) as f32).clamp(1667.0, 25000.0);
+    let t = 1000.0 / kelvin;
+    let (t2, t3) = (t * t, t * t * t);
+
+    let x = if kelvin <= 4000.0 {
+        -0.2661239 * t3 - 0.2343589 * t2 + 0.8776956 * t + 0.179910
+    } else {
+        -3.0258469 * t3 + 2.1070379 * t2 + 0.2226347 * t + 0.240390
+    };
+
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if kelvin <= 2222.0 {
+        -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+    } else if kelvin <= 4000.0 {
+        -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+    };
+
+    (x, y)
+}
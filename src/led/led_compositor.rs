@@ -0,0 +1,185 @@
+use embassy_time::Instant;
+
+use crate::led::led_colour::Rgb16;
+use crate::led::led_indication::Indication;
+
+/// Layers shown on top of the light, from lowest to highest priority.
+///
+/// The light itself forms the bottom two layers: its colour and level, and above that the
+/// effect of its [`Mode`](crate::led::led_driver::Mode). Each overlay hides everything below it
+/// while it shows an [`Indication`], and hands the LED back to the next active layer down once
+/// the indication finishes or is cleared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Overlay {
+    /// Alerts such as a doorbell flash.
+    Notification,
+    /// Identification requested by a controller.
+    Identify,
+    /// Commissioning and network status patterns.
+    Status,
+    /// Device maintenance such as a factory reset, shown above everything else.
+    Maintenance,
+}
+
+impl Overlay {
+    pub const COUNT: usize = 4;
+}
+
+/// The indications shown on each [`Overlay`], and when they started.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Compositor {
+    layers: [Option<(Indication, Instant)>; Overlay::COUNT],
+}
+
+impl Compositor {
+    /// Shows `indication` on `overlay` from `now`, or clears the overlay if `None`.
+    pub fn set(&mut self, overlay: Overlay, indication: Option<Indication>, now: Instant) {
+        self.layers[overlay as usize] = indication.map(|indication| (indication, now));
+    }
+
+    pub fn get(&self, overlay: Overlay) -> Option<Indication> {
+        self.layers[overlay as usize].map(|(indication, _)| indication)
+    }
+
+    /// Lets the indication on `overlay` end with the cycle running at `now`, see
+    /// [`Indication::finishing`].
+    pub fn finish(&mut self, overlay: Overlay, now: Instant) {
+        let layer = &mut self.layers[overlay as usize];
+        *layer = layer.and_then(|(indication, started)| {
+            let finishing = indication.finishing(now.saturating_duration_since(started))?;
+            Some((finishing, started))
+        });
+    }
+
+    /// Clears every overlay below `overlay`.
+    pub fn clear_below(&mut self, overlay: Overlay) {
+        self.layers[..overlay as usize].fill(None);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.layers.iter().any(Option::is_some)
+    }
+
+    /// The colour and output of the highest active overlay at `now`, or `None` if the light
+    /// shows through. Overlays whose indication has finished are cleared.
+    pub fn frame(&mut self, now: Instant) -> Option<(Rgb16, u16)> {
+        for layer in self.layers.iter_mut().rev() {
+            if let Some((indication, started)) = *layer {
+                match indication.frame(now.saturating_duration_since(started)) {
+                    Some(frame) => return Some(frame),
+                    None => *layer = None,
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_time::Duration;
+
+    use super::*;
+    use crate::led::led_indication::Repeat;
+    use crate::test_util::at;
+
+    const RED: Rgb16 = Rgb16::new(u16::MAX, 0, 0);
+    const WHITE: Rgb16 = Rgb16::new(u16::MAX, u16::MAX, u16::MAX);
+
+    /// Shows `colour` for one second, then turns off for one.
+    fn flash(colour: Rgb16, count: u16) -> Indication {
+        Indication::Flash {
+            colour,
+            period: Duration::from_secs(2),
+            repeat: Repeat::Count(count),
+        }
+    }
+
+    #[test]
+    fn light_shows_without_overlays() {
+        let mut compositor = Compositor::default();
+        assert!(!compositor.is_active());
+        assert_eq!(compositor.frame(at(0)), None);
+    }
+
+    #[test]
+    fn higher_overlays_take_priority() {
+        let mut compositor = Compositor::default();
+        compositor.set(Overlay::Notification, Some(flash(RED, 5)), at(0));
+        compositor.set(Overlay::Identify, Some(flash(WHITE, 1)), at(100));
+        assert_eq!(compositor.frame(at(200)), Some((WHITE, u16::MAX)));
+
+        // Lower overlays never cover a higher one, whenever they start.
+        compositor.set(Overlay::Notification, Some(flash(RED, 5)), at(300));
+        assert_eq!(compositor.frame(at(400)), Some((WHITE, u16::MAX)));
+
+        // Status patterns hide identification.
+        compositor.set(Overlay::Status, Some(Indication::Calibrating), at(500));
+        assert_eq!(
+            compositor.frame(at(500)).unwrap().0,
+            Rgb16::new(0, u16::MAX, u16::MAX)
+        );
+    }
+
+    #[test]
+    fn finished_overlays_hand_back_to_the_next_layer() {
+        let mut compositor = Compositor::default();
+        compositor.set(Overlay::Notification, Some(flash(RED, 5)), at(0));
+        compositor.set(Overlay::Identify, Some(flash(WHITE, 1)), at(1000));
+        assert_eq!(compositor.frame(at(1500)), Some((WHITE, u16::MAX)));
+
+        // The identification ends after its flash, and the notification carries on where it
+        // would have been, in its third flash.
+        assert_eq!(compositor.frame(at(4500)), Some((RED, u16::MAX)));
+        assert_eq!(compositor.get(Overlay::Identify), None);
+
+        // Once that finishes too, the light shows through.
+        assert_eq!(compositor.frame(at(10_000)), None);
+        assert!(!compositor.is_active());
+    }
+
+    #[test]
+    fn cleared_overlays_hand_back_to_the_next_layer() {
+        let mut compositor = Compositor::default();
+        compositor.set(Overlay::Identify, Some(flash(WHITE, 10)), at(0));
+        compositor.set(Overlay::Maintenance, Some(flash(RED, 10)), at(0));
+        assert_eq!(compositor.frame(at(0)), Some((RED, u16::MAX)));
+
+        compositor.set(Overlay::Maintenance, None, at(100));
+        assert_eq!(compositor.frame(at(100)), Some((WHITE, u16::MAX)));
+    }
+
+    #[test]
+    fn clear_below_keeps_the_higher_overlays() {
+        let mut compositor = Compositor::default();
+        compositor.set(Overlay::Notification, Some(flash(RED, 10)), at(0));
+        compositor.set(Overlay::Identify, Some(flash(RED, 10)), at(0));
+        compositor.set(Overlay::Status, Some(Indication::NetworkLost), at(0));
+
+        compositor.clear_below(Overlay::Status);
+        assert_eq!(compositor.get(Overlay::Notification), None);
+        assert_eq!(compositor.get(Overlay::Identify), None);
+        assert_eq!(
+            compositor.get(Overlay::Status),
+            Some(Indication::NetworkLost)
+        );
+    }
+
+    #[test]
+    fn finishing_ends_with_the_current_cycle() {
+        let mut compositor = Compositor::default();
+        compositor.set(Overlay::Identify, Some(flash(WHITE, 15)), at(0));
+
+        // Asked to finish during the third flash, which lasts until 6 s.
+        compositor.finish(Overlay::Identify, at(4500));
+        assert_eq!(compositor.frame(at(4500)), Some((WHITE, u16::MAX)));
+        assert_eq!(compositor.frame(at(5999)), Some((WHITE, 0)));
+        assert_eq!(compositor.frame(at(6000)), None);
+
+        // Patterns without cycles end at once.
+        compositor.set(Overlay::Identify, Some(Indication::ResetConfirmed), at(0));
+        compositor.finish(Overlay::Identify, at(100));
+        assert_eq!(compositor.get(Overlay::Identify), None);
+    }
+}
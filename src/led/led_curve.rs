@@ -0,0 +1,351 @@
+#[cfg(feature = "defmt")]
+use defmt::{error, info};
+#[cfg(feature = "log")]
+use log::{error, info};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use rs_matter_embassy::matter::error::Error;
+use rs_matter_embassy::stack::persist::KvBlobStore;
+
+use crate::settings::{Decoder, Encoder, Setting, SettingKey, Settings};
+
+/// Highest level of the Matter LevelControl cluster.
+const MAX_LEVEL: f32 = 254.0;
+
+/// Shapes of the mapping from level to light output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Curve {
+    /// Output proportional to the level.
+    Linear,
+    /// Even steps of perceived lightness, following the CIE 1931 L* formula.
+    Cie1931,
+    /// The DALI dimming curve, spanning 0.1% to 100% output in even ratios.
+    Logarithmic,
+    /// A user-supplied table of outputs, interpolated between its points.
+    Lut(CurvePoints),
+}
+
+/// Most points of a [`CurvePoints`] table.
+pub const MAX_CURVE_POINTS: usize = 16;
+
+/// Outputs at up to [`MAX_CURVE_POINTS`] levels, with `u16::MAX` being full output. Levels
+/// between two points are interpolated linearly, and levels outside the points take the output
+/// of the nearest one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CurvePoints {
+    levels: [u8; MAX_CURVE_POINTS],
+    outputs: [u16; MAX_CURVE_POINTS],
+    len: u8,
+}
+
+impl CurvePoints {
+    /// A table of `(level, output)` points, or `None` if there are none, more than
+    /// [`MAX_CURVE_POINTS`], or their levels do not rise from point to point.
+    pub fn new(points: &[(u8, u16)]) -> Option<Self> {
+        let rising = points.windows(2).all(|pair| pair[0].0 < pair[1].0);
+        if points.is_empty() || points.len() > MAX_CURVE_POINTS || !rising {
+            return None;
+        }
+
+        let mut table = Self {
+            levels: [0; MAX_CURVE_POINTS],
+            outputs: [0; MAX_CURVE_POINTS],
+            len: points.len() as u8,
+        };
+        for (index, &(level, output)) in points.iter().enumerate() {
+            table.levels[index] = level;
+            table.outputs[index] = output;
+        }
+        Some(table)
+    }
+
+    /// The `(level, output)` points of the table.
+    pub fn points(&self) -> impl Iterator<Item = (u8, u16)> + '_ {
+        let len = self.len as usize;
+        self.levels[..len]
+            .iter()
+            .copied()
+            .zip(self.outputs[..len].iter().copied())
+    }
+
+    /// Output for `level`, from 0.0 to 1.0.
+    fn output(&self, level: u8) -> f32 {
+        let len = self.len as usize;
+        let (levels, outputs) = (&self.levels[..len], &self.outputs[..len]);
+        let output = match levels.iter().position(|&point| point >= level) {
+            Some(0) => outputs[0] as f32,
+            Some(index) => {
+                let (start, end) = (levels[index - 1] as f32, levels[index] as f32);
+                let (from, to) = (outputs[index - 1] as f32, outputs[index] as f32);
+                from + (to - from) * (level as f32 - start) / (end - start)
+            }
+            None => outputs[len - 1] as f32,
+        };
+        output / u16::MAX as f32
+    }
+}
+
+impl Curve {
+    /// Output for `level`, from 0.0 to 1.0.
+    fn output(&self, level: u8) -> f32 {
+        let x = (level as f32 / MAX_LEVEL).min(1.0);
+
+        match self {
+            Self::Linear => x,
+            Self::Cie1931 => {
+                let lightness = x * 100.0;
+                if lightness <= 8.0 {
+                    lightness / 903.3
+                } else {
+                    let y = (lightness + 16.0) / 116.0;
+                    y * y * y
+                }
+            }
+            Self::Logarithmic => libm::powf(10.0, (level.max(1) as f32 - 1.0) * 3.0 / 253.0 - 3.0),
+            Self::Lut(points) => points.output(level),
+        }
+    }
+}
+
+/// Maps levels to the light output of the fixture.
+///
+/// Every level above 0 is clamped to `min_output..=max_output`, with `u16::MAX` being full
+/// output. Raising `min_output` keeps the lowest levels usable as a night light on fixtures that
+/// would otherwise barely glow, while level 0 still switches the light off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BrightnessCurve {
+    pub curve: Curve,
+    pub min_output: u16,
+    pub max_output: u16,
+}
+
+impl Default for BrightnessCurve {
+    /// The level is applied as is.
+    fn default() -> Self {
+        Self {
+            curve: Curve::Linear,
+            min_output: 0,
+            max_output: u16::MAX,
+        }
+    }
+}
+
+impl Setting for BrightnessCurve {
+    const KEY: SettingKey = SettingKey::BrightnessCurve;
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let curve: u8 = match self.curve {
+            Curve::Linear => 0,
+            Curve::Cie1931 => 1,
+            Curve::Logarithmic => 2,
+            Curve::Lut(_) => 3,
+        };
+
+        let mut encoder = Encoder::new(buf);
+        encoder.put(&[curve])?;
+        encoder.put(&self.min_output.to_le_bytes())?;
+        encoder.put(&self.max_output.to_le_bytes())?;
+        // The points of a table follow the limits.
+        if let Curve::Lut(points) = self.curve {
+            encoder.put(&[points.len])?;
+            for (level, output) in points.points() {
+                encoder.put(&[level])?;
+                encoder.put(&output.to_le_bytes())?;
+            }
+        }
+        Ok(encoder.written())
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(data);
+        let curve = decoder.take::<1>()?;
+        let min_output = u16::from_le_bytes(decoder.take()?);
+        let max_output = u16::from_le_bytes(decoder.take()?);
+
+        let curve = match curve {
+            [0] => Curve::Linear,
+            [1] => Curve::Cie1931,
+            [2] => Curve::Logarithmic,
+            [3] => {
+                let [len] = decoder.take()?;
+                let mut points = [(0, 0); MAX_CURVE_POINTS];
+                for point in points.get_mut(..len as usize)? {
+                    let [level] = decoder.take()?;
+                    *point = (level, u16::from_le_bytes(decoder.take()?));
+                }
+                Curve::Lut(CurvePoints::new(&points[..len as usize])?)
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            curve,
+            min_output,
+            max_output,
+        })
+    }
+}
+
+/// Signals a [`BrightnessCurve`] to be saved by [`persist_brightness_curve`].
+pub type CurveSignal = Signal<CriticalSectionRawMutex, BrightnessCurve>;
+
+/// Saves each curve signalled through `updates`.
+pub async fn persist_brightness_curve<S: KvBlobStore>(
+    settings: &Settings<S>,
+    updates: &CurveSignal,
+) -> ! {
+    loop {
+        let curve = updates.wait().await;
+
+        match settings.save(&curve).await {
+            Ok(()) => info!("Saved brightness curve"),
+            Err(e) => error!("Failed to save brightness curve: {}", e),
+        }
+    }
+}
+
+/// A [`BrightnessCurve`] evaluated for every level.
+pub struct LevelMap {
+    curve: BrightnessCurve,
+    outputs: [u16; 256],
+}
+
+impl LevelMap {
+    pub fn new(curve: BrightnessCurve) -> Self {
+        let (min, max) = (
+            curve.min_output.min(curve.max_output),
+            curve.max_output.max(curve.min_output),
+        );
+
+        let mut outputs = [0; 256];
+        for (level, output) in outputs.iter_mut().enumerate().skip(1) {
+            let value = curve.curve.output(level as u8).clamp(0.0, 1.0);
+            *output = ((value * u16::MAX as f32 + 0.5) as u16).clamp(min, max);
+        }
+
+        Self { curve, outputs }
+    }
+
+    pub fn curve(&self) -> BrightnessCurve {
+        self.curve
+    }
+
+    /// Output for `level`, with `u16::MAX` being full output.
+    pub fn output(&self, level: u8) -> u16 {
+        self.outputs[level as usize]
+    }
+}
+
+impl Default for LevelMap {
+    fn default() -> Self {
+        Self::new(BrightnessCurve::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(curve: Curve, min_output: u16, max_output: u16) -> LevelMap {
+        LevelMap::new(BrightnessCurve {
+            curve,
+            min_output,
+            max_output,
+        })
+    }
+
+    #[test]
+    fn level_zero_is_off_despite_the_minimum() {
+        let map = map(Curve::Cie1931, 1000, u16::MAX);
+        assert_eq!(map.output(0), 0);
+        assert_eq!(map.output(1), 1000);
+    }
+
+    #[test]
+    fn outputs_are_clamped_to_the_range() {
+        let map = map(Curve::Linear, 1000, 40000);
+        assert_eq!(map.output(1), 1000);
+        assert_eq!(map.output(127), 32768);
+        assert_eq!(map.output(254), 40000);
+        assert_eq!(map.output(255), 40000);
+    }
+
+    #[test]
+    fn swapped_limits_are_put_in_order() {
+        assert_eq!(
+            map(Curve::Linear, 40000, 1000).outputs,
+            map(Curve::Linear, 1000, 40000).outputs
+        );
+    }
+
+    #[test]
+    fn curves_span_the_full_output() {
+        for curve in [
+            Curve::Linear,
+            Curve::Cie1931,
+            Curve::Logarithmic,
+            Curve::Lut(CurvePoints::new(&[(1, 100), (100, 20000), (254, u16::MAX)]).unwrap()),
+        ] {
+            let map = map(curve, 0, u16::MAX);
+            assert_eq!(map.output(254), u16::MAX, "{curve:?}");
+
+            // Every curve rises with the level.
+            for level in 1..=254 {
+                assert!(map.output(level) >= map.output(level - 1), "{curve:?}");
+            }
+        }
+
+        // The logarithmic curve starts at 0.1% output.
+        assert_eq!(map(Curve::Logarithmic, 0, u16::MAX).output(1), 66);
+    }
+
+    #[test]
+    fn tables_interpolate_between_their_points() {
+        let points = CurvePoints::new(&[(10, 1000), (20, 3000), (200, 3000), (254, 60000)]);
+        let map = map(Curve::Lut(points.unwrap()), 0, u16::MAX);
+
+        // Levels outside the points take the output of the nearest one.
+        assert_eq!(map.output(1), 1000);
+        assert_eq!(map.output(10), 1000);
+        assert_eq!(map.output(15), 2000);
+        assert_eq!(map.output(20), 3000);
+        assert_eq!(map.output(150), 3000);
+        assert_eq!(map.output(254), 60000);
+        assert_eq!(map.output(255), 60000);
+    }
+
+    #[test]
+    fn tables_need_rising_levels() {
+        assert_eq!(CurvePoints::new(&[]), None);
+        assert_eq!(CurvePoints::new(&[(10, 0), (10, 100)]), None);
+        assert_eq!(CurvePoints::new(&[(20, 0), (10, 100)]), None);
+        assert_eq!(CurvePoints::new(&[(1, 0); MAX_CURVE_POINTS + 1][..]), None);
+
+        let points: Vec<_> = (0..MAX_CURVE_POINTS as u8).map(|i| (i * 16, 0)).collect();
+        assert!(CurvePoints::new(&points).is_some());
+    }
+
+    #[test]
+    fn curves_round_trip() {
+        let mut buf = [0; 64];
+        let curve = BrightnessCurve {
+            curve: Curve::Logarithmic,
+            min_output: 300,
+            max_output: 60000,
+        };
+        let len = curve.encode(&mut buf).unwrap();
+        assert_eq!(BrightnessCurve::decode(&buf[..len]), Some(curve));
+
+        let points: Vec<_> = (0..MAX_CURVE_POINTS as u8)
+            .map(|i| (i * 16 + 1, i as u16 * 4000))
+            .collect();
+        let lut = BrightnessCurve {
+            curve: Curve::Lut(CurvePoints::new(&points).unwrap()),
+            ..curve
+        };
+        let len = lut.encode(&mut buf).unwrap();
+        assert_eq!(BrightnessCurve::decode(&buf[..len]), Some(lut));
+        assert_eq!(BrightnessCurve::decode(&buf[..len - 1]), None);
+    }
+}
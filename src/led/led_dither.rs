@@ -0,0 +1,181 @@
+use crate::led::led_colour::Rgb16;
+
+/// Lowest rate, in hertz, at which a channel may alternate between two output steps. Slower
+/// alternation shows as flicker, worst at the dimmest levels where one step is a large part of
+/// the output; the eye stops resolving it at around 20 Hz there.
+const MIN_FLICKER_FREQUENCY: u32 = 20;
+
+/// Time, in seconds, after which a colour held unchanged stops being dithered. It is then shown
+/// at the nearest output step, so that the driver can stop rendering.
+const SETTLE_TIME: u32 = 2;
+
+/// Reduces 16-bit drive values to the resolution of the output through temporal dithering.
+///
+/// Each channel accumulates the part of its value that falls between two output steps, and
+/// steps up for a frame whenever a whole step has built up, so that over consecutive frames the
+/// output averages out to the drive value. Only values for which the steps alternate fast enough
+/// not to flicker at the frame rate are dithered; the others, and colours held for
+/// [`SETTLE_TIME`], are rounded to the nearest step.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Dither {
+    /// The error carried over for each channel, in 65535ths of an output step.
+    error: [u32; 3],
+    /// The colour of the last frame, and the number of frames it was held for since.
+    held: Rgb16,
+    held_frames: u32,
+}
+
+impl Dither {
+    /// Returns the output for this frame with `bits` per channel, scaled back to 16 bits, and
+    /// whether the output alternates between steps and therefore needs to be refreshed every
+    /// frame, rendered at `frame_rate` frames per second.
+    pub fn next(&mut self, colour: Rgb16, bits: u32, frame_rate: u32) -> (Rgb16, bool) {
+        let frame_rate = frame_rate.max(1);
+        if colour == self.held {
+            self.held_frames = self.held_frames.saturating_add(1);
+        } else {
+            self.held = colour;
+            self.held_frames = 0;
+        }
+        let settled = self.held_frames >= frame_rate * SETTLE_TIME;
+
+        // A fraction `f` of a step alternates at `min(f, 1 - f)` times the frame rate.
+        let step = u16::MAX as u32;
+        let min_fraction = (MIN_FLICKER_FREQUENCY * step).div_ceil(frame_rate);
+        let levels = (1u32 << bits.clamp(1, 16)) - 1;
+        let mut channel = |index: usize, value: u16| {
+            // Both fit in 32 bits, as the error stays below a step.
+            let scaled = value as u32 * levels;
+            let fraction = scaled % step;
+
+            let (output, dithered) =
+                if settled || fraction < min_fraction || step - fraction < min_fraction {
+                    self.error[index] = 0;
+                    ((scaled + step / 2) / step, false)
+                } else {
+                    let sum = scaled + self.error[index];
+                    self.error[index] = sum % step;
+                    (sum / step, true)
+                };
+
+            let value = (output * step + levels / 2) / levels;
+            (value as u16, dithered)
+        };
+
+        let (r, r_dithered) = channel(0, colour.r);
+        let (g, g_dithered) = channel(1, colour.g);
+        let (b, b_dithered) = channel(2, colour.b);
+
+        (Rgb16::new(r, g, b), r_dithered || g_dithered || b_dithered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::led::led_curve::{BrightnessCurve, Curve, LevelMap};
+
+    const FRAME_RATE: u32 = 50;
+
+    /// Frames shown before a held colour settles.
+    const UNSETTLED_FRAMES: u32 = FRAME_RATE * SETTLE_TIME;
+
+    /// The red channel output over `frames` frames of `value`, summed at 16 bits.
+    fn total(value: u16, bits: u32, frame_rate: u32, frames: u32) -> u64 {
+        let mut dither = Dither::default();
+        (0..frames)
+            .map(|_| dither.next(Rgb16::new(value, 0, 0), bits, frame_rate).0.r as u64)
+            .sum()
+    }
+
+    #[test]
+    fn averages_to_the_input_while_dithered() {
+        // Over 8 bits a step is 257. These values lie close to half way between two steps.
+        for value in [128, 129, 385, 1156, 29941] {
+            let frames = UNSETTLED_FRAMES;
+            let total = total(value, 8, FRAME_RATE, frames);
+            assert!(
+                total.abs_diff(value as u64 * frames as u64) < 257,
+                "{value}: {total}"
+            );
+        }
+    }
+
+    #[test]
+    fn faster_frame_rates_dither_smaller_fractions() {
+        // A quarter of an 8-bit step alternates at 12.5 Hz at 50 frames per second, and is
+        // rounded, but at 100 Hz at 400 frames per second.
+        let mut dither = Dither::default();
+        let outputs: Vec<u16> = (0..8)
+            .map(|_| dither.next(Rgb16::new(65, 0, 0), 8, 400).0.r)
+            .collect();
+        assert_eq!(outputs, [0, 0, 0, 257, 0, 0, 0, 257]);
+
+        let mut dither = Dither::default();
+        assert_eq!(
+            dither.next(Rgb16::new(65, 0, 0), 8, FRAME_RATE),
+            (Rgb16::new(0, 0, 0), false)
+        );
+        assert_eq!(
+            dither.next(Rgb16::new(200, 0, 0), 8, FRAME_RATE),
+            (Rgb16::new(257, 0, 0), false)
+        );
+    }
+
+    #[test]
+    fn lowest_logarithmic_level_holds_steady() {
+        let map = LevelMap::new(BrightnessCurve {
+            curve: Curve::Logarithmic,
+            ..BrightnessCurve::default()
+        });
+        let value = map.output(1);
+        // About a quarter of an 8-bit step.
+        assert_eq!(value / 257, 0);
+        assert!(value > 257 / 5);
+
+        let mut dither = Dither::default();
+        let first = dither.next(Rgb16::new(value, value, value), 8, FRAME_RATE);
+        assert!(!first.1);
+        for _ in 0..UNSETTLED_FRAMES * 2 {
+            assert_eq!(
+                dither.next(Rgb16::new(value, value, value), 8, FRAME_RATE),
+                first
+            );
+        }
+    }
+
+    #[test]
+    fn held_colours_settle_on_the_nearest_step() {
+        let colour = Rgb16::new(140, 0, 0);
+        let mut dither = Dither::default();
+        for _ in 0..UNSETTLED_FRAMES {
+            assert!(dither.next(colour, 8, FRAME_RATE).1);
+        }
+        for _ in 0..10 {
+            assert_eq!(
+                dither.next(colour, 8, FRAME_RATE),
+                (Rgb16::new(257, 0, 0), false)
+            );
+        }
+
+        // A new colour is dithered again.
+        assert!(dither.next(Rgb16::new(130, 0, 0), 8, FRAME_RATE).1);
+    }
+
+    #[test]
+    fn exact_steps_are_not_dithered() {
+        let mut dither = Dither::default();
+        for _ in 0..4 {
+            let (output, dithered) = dither.next(Rgb16::new(0, 257 * 3, u16::MAX), 8, FRAME_RATE);
+            assert_eq!(output, Rgb16::new(0, 257 * 3, u16::MAX));
+            assert!(!dithered);
+        }
+    }
+
+    #[test]
+    fn full_resolution_outputs_pass_values_through() {
+        let mut dither = Dither::default();
+        let colour = Rgb16::new(1, 12345, u16::MAX);
+        assert_eq!(dither.next(colour, 16, FRAME_RATE), (colour, false));
+    }
+}
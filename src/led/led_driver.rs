@@ -1,26 +1,28 @@
 use core::cell::{Cell, RefCell};
 
-use embassy_futures::select::{Either, select};
+use embassy_futures::select::{Either3, select3};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::channel::{Receiver, Sender};
-use embassy_time::{Duration, Timer};
+use embassy_sync::watch::Watch;
+use embassy_time::{Duration, Instant, Timer};
 
 #[cfg(feature = "defmt")]
-use defmt::{debug, error, warn};
+use defmt::{debug, error, info, warn};
 #[cfg(feature = "log")]
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
 
-use esp_hal::{
-    gpio::AnyPin,
-    peripherals,
-    rmt::{PulseCode, Rmt},
-    time::Rate,
-};
-use esp_hal_smartled::{LedAdapterError, SmartLedsAdapterAsync, buffer_size_async};
-use smart_leds::{
-    RGB8, SmartLedsWriteAsync, brightness, gamma,
-    hsv::{Hsv, hsv2rgb},
-};
+use smart_leds::RGB8;
+
+use crate::led::led_calibration::{Calibration, Correction};
+use crate::led::led_channel::LedReceiver;
+use crate::led::led_colour::Rgb16;
+use crate::led::led_compositor::{Compositor, Overlay};
+use crate::led::led_curve::{BrightnessCurve, LevelMap};
+use crate::led::led_dither::Dither;
+use crate::led::led_frame::{DEFAULT_FRAME_RATE, FrameClock, FrameMonitor};
+use crate::led::led_indication::Indication;
+use crate::led::led_output::LedOutput;
+use crate::led::led_power::PowerBudget;
+use crate::led::led_transition::Transition;
 
 /// Defines the behaviour of the light.
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -33,82 +35,393 @@ pub enum Mode {
     ColourChanging { duration: Duration },
 }
 
+/// The animations of the OnOff cluster's `OffWithEffect` command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OffEffect {
+    /// Fades to off in 0.8 seconds.
+    Fade,
+    /// Switches off at once.
+    NoFade,
+    /// Dims down by 50% in 0.8 seconds, then fades to off in 12 seconds.
+    DimThenFade,
+    /// Brightens by 20% in 0.5 seconds, then fades to off in 1 second.
+    DyingLight,
+}
+
+impl OffEffect {
+    /// The steps of the effect, each a target in percent of the output when the effect started,
+    /// and the time taken to reach it.
+    fn steps(&self) -> &'static [(u8, Duration)] {
+        const FADE: &[(u8, Duration)] = &[(0, Duration::from_millis(800))];
+        const DIM_THEN_FADE: &[(u8, Duration)] = &[
+            (50, Duration::from_millis(800)),
+            (0, Duration::from_secs(12)),
+        ];
+        const DYING_LIGHT: &[(u8, Duration)] = &[
+            (120, Duration::from_millis(500)),
+            (0, Duration::from_secs(1)),
+        ];
+
+        match self {
+            Self::Fade => FADE,
+            Self::NoFade => &[],
+            Self::DimThenFade => DIM_THEN_FADE,
+            Self::DyingLight => DYING_LIGHT,
+        }
+    }
+
+    /// The time taken by the whole effect.
+    pub fn duration(&self) -> Duration {
+        self.steps()
+            .iter()
+            .fold(Duration::from_ticks(0), |total, (_, duration)| {
+                total + *duration
+            })
+    }
+}
+
+/// A running [`OffEffect`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct OffFade {
+    effect: OffEffect,
+    /// The output when the effect started.
+    from: u16,
+    step: u8,
+    /// The output, replacing the level and dimmer until the effect finishes.
+    output: Transition<u16>,
+}
+
+impl OffFade {
+    /// Starts `effect` from the output `from`, or returns `None` if it has no steps.
+    ///
+    /// The first step starts here rather than in the effect task, so that the progress
+    /// published for the command already covers it.
+    fn start(effect: OffEffect, from: u16) -> Option<Self> {
+        let &(percent, duration) = effect.steps().first()?;
+        Some(Self {
+            effect,
+            from,
+            step: 0,
+            output: Transition::new(from, Self::step_target(from, percent), duration),
+        })
+    }
+
+    /// The output reached at the end of a step to `percent` of `from`.
+    fn step_target(from: u16, percent: u8) -> u16 {
+        (from as u32 * percent as u32 / 100).min(u16::MAX as u32) as u16
+    }
+
+    /// The time left until the effect finishes.
+    fn remaining(&self, now: Instant) -> Duration {
+        let later = self.effect.steps().iter().skip(self.step as usize + 1);
+        later.fold(self.output.remaining(now), |total, (_, duration)| {
+            total + *duration
+        })
+    }
+}
+
+/// Commands sent to the [`Driver`].
+///
+/// Colour and brightness changes carry a target and a duration. The driver interpolates towards
+/// the target at a fixed frame rate, starting from whatever the LED currently shows.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ControlMessage {
-    SetOn(bool),
-    SetBrightness(u8),
-    SetColour { r: u8, g: u8, b: u8 },
+    /// Switches the LED on or off, fading the output over `transition`.
+    SetOn {
+        on: bool,
+        transition: Duration,
+    },
+    /// Switches the LED off with an animation. Switching off again lets it finish.
+    OffWithEffect(OffEffect),
+    SetBrightness {
+        level: u8,
+        transition: Duration,
+    },
+    /// Sets the colour, given with 16 bits per channel.
+    SetColour {
+        r: u16,
+        g: u16,
+        b: u16,
+        transition: Duration,
+    },
     SetMode(Mode),
+    /// Replaces the per-device correction of the LED channels.
+    SetCalibration(Calibration),
+    /// Replaces the mapping from level to light output.
+    SetBrightnessCurve(BrightnessCurve),
+    /// Renders transitions and effects at the given frames per second.
+    SetFrameRate(u32),
+    /// Shows an indication on `overlay`, or clears the overlay if `None`.
+    Indicate {
+        overlay: Overlay,
+        indication: Option<Indication>,
+    },
+    /// Lets the indication on the overlay end with its current cycle.
+    FinishIndication(Overlay),
+    /// Returns to the state at power-on: on at [`DEFAULT_LEVEL`] in [`DEFAULT_COLOUR`] with the
+    /// [`DEFAULT_MODE`], without notifications or identification. The status and maintenance
+    /// overlays are left to the tasks driving them, and indications sent before the reset but
+    /// not yet picked up are still shown.
     Reset,
 }
 
-pub type LedSender<'a> = Sender<'a, CriticalSectionRawMutex, ControlMessage, 4>;
-pub type LedReceiver<'a> = Receiver<'a, CriticalSectionRawMutex, ControlMessage, 4>;
+/// Time remaining on each of the driver's transitions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Progress {
+    pub on_off: Duration,
+    pub level: Duration,
+    pub colour: Duration,
+}
 
-pub struct Driver<'a> {
-    led: RefCell<SmartLedsAdapterAsync<'a, 25>>,
+/// Error conditions reported by the [`Driver`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// Writing to the LED failed repeatedly. The LED keeps showing the last frame written.
+    Write,
+}
+
+/// The state applied by the [`Driver`], published back to the handlers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LedState {
+    pub on: bool,
+    /// Target level of the light, excluding on/off fades and effects.
+    pub level: u8,
+    /// Target colour of the light, excluding effects.
+    pub colour: Rgb16,
+    pub mode: Mode,
+    pub progress: Progress,
+    pub calibration: Calibration,
+    /// Whether the output is currently scaled down to stay within the [`PowerBudget`].
+    pub power_limited: bool,
+    /// Frames per second at which transitions and effects are rendered.
+    pub frame_rate: u32,
+    pub fault: Option<Fault>,
+}
+
+/// Number of receivers of the [`LedFeedback`], one for each of its consumers:
+/// - `OnOffHooks::run` of the `LedHandler`, following the driver switching on and off,
+/// - `LevelControlHooks::run` of the `LedHandler`, following the driver's level,
+/// - `persist_calibration`, saving the calibration the driver applied.
+pub const LED_FEEDBACK_RECEIVERS: usize = 3;
+
+/// Carries the [`LedState`] from the driver to the handlers.
+pub type LedFeedback = Watch<CriticalSectionRawMutex, LedState, LED_FEEDBACK_RECEIVERS>;
+
+/// Number of times a frame is written before the driver reports a [`Fault::Write`].
+const WRITE_ATTEMPTS: u8 = 3;
+
+/// Delay between failed writes of the same frame.
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(5);
+
+/// Interval at which a faulted driver tries to write to the LED again.
+const FAULT_RETRY_PERIOD: Duration = Duration::from_secs(1);
+
+// The light at power-on and after a `ControlMessage::Reset`, until told otherwise.
+
+/// The default level.
+pub const DEFAULT_LEVEL: u8 = 150;
+
+/// The default mode, slowly cycling through the hues.
+pub const DEFAULT_MODE: Mode = Mode::ColourChanging {
+    duration: Duration::from_secs(10),
+};
+
+/// The default colour, a warm white, from which the default mode starts.
+pub const DEFAULT_COLOUR: RGB8 = RGB8 {
+    r: 239,
+    g: 235,
+    b: 216,
+};
+
+/// Lowest output scale reached by [`Mode::Pulse`].
+const PULSE_MIN: u8 = 8;
+
+/// The colours visited by [`Mode::ColourChanging`].
+/// Fading linearly between neighbours sweeps through every fully saturated hue.
+const HUE_WHEEL: [Rgb16; 6] = [
+    Rgb16::new(u16::MAX, 0, 0),
+    Rgb16::new(u16::MAX, u16::MAX, 0),
+    Rgb16::new(0, u16::MAX, 0),
+    Rgb16::new(0, u16::MAX, u16::MAX),
+    Rgb16::new(0, 0, u16::MAX),
+    Rgb16::new(u16::MAX, 0, u16::MAX),
+];
+
+pub struct Driver<'a, O: LedOutput> {
+    output: RefCell<O>,
     receiver: LedReceiver<'a>,
-    level: Cell<u8>,
-    colour: Cell<RGB8>,
+    feedback: &'a LedFeedback,
+    fault: Cell<Option<Fault>>,
+    on: Cell<bool>,
+    /// Output scale applied on top of `level`. Faded between 0 and `u16::MAX` when switching on
+    /// and off.
+    dimmer: Cell<Transition<u16>>,
+    level: Cell<Transition<u8>>,
+    colour: Cell<Transition<Rgb16>>,
+    /// Output scale driven by the running effect.
+    effect_level: Cell<Transition<u8>>,
+    /// Colour driven by the running effect. Takes precedence over `colour` while set.
+    effect_colour: Cell<Option<Transition<Rgb16>>>,
+    /// Position within the running effect, kept so that the effect resumes where it paused.
+    effect_step: Cell<u8>,
+    off_fade: Cell<Option<OffFade>>,
     mode: Mode,
+    /// The overlays shown on top of the light.
+    compositor: Cell<Compositor>,
+    correction: Correction,
+    level_map: LevelMap,
+    power_budget: Option<PowerBudget>,
+    power_limited: Cell<bool>,
+    dither: Cell<Dither>,
+    /// Whether the last frame was dithered, so that the following frames must be rendered too.
+    dithering: Cell<bool>,
+    clock: Cell<FrameClock>,
+    /// The frame last written to the output, if the write succeeded.
+    last_written: Cell<Option<Rgb16>>,
+    monitor: Option<&'a FrameMonitor>,
 }
 
-impl<'a> Driver<'a> {
-    pub fn new(rmt: peripherals::RMT<'a>, pin: AnyPin<'a>, receiver: LedReceiver<'a>) -> Self {
-        // Setup the LED
-        // Configure RMT (Remote Control Transceiver) peripheral globally
-        // <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/peripherals/rmt.html>
-        let rmt: Rmt<'_, esp_hal::Async> = {
-            let frequency: Rate = { Rate::from_mhz(80) };
-            Rmt::new(rmt, frequency)
+impl<'a, O: LedOutput> Driver<'a, O> {
+    pub fn new(
+        output: O,
+        calibration: Calibration,
+        receiver: LedReceiver<'a>,
+        feedback: &'a LedFeedback,
+    ) -> Self {
+        Self {
+            output: RefCell::new(output),
+            receiver,
+            feedback,
+            fault: Cell::new(None),
+            on: Cell::new(true),
+            dimmer: Cell::new(Transition::fixed(u16::MAX)),
+            colour: Cell::new(Transition::fixed(Rgb16::from(DEFAULT_COLOUR))),
+            level: Cell::new(Transition::fixed(DEFAULT_LEVEL)),
+            effect_level: Cell::new(Transition::fixed(u8::MAX)),
+            effect_colour: Cell::new(None),
+            effect_step: Cell::new(0),
+            off_fade: Cell::new(None),
+            mode: DEFAULT_MODE,
+            compositor: Cell::new(Compositor::default()),
+            correction: Correction::new(calibration),
+            level_map: LevelMap::default(),
+            power_budget: None,
+            power_limited: Cell::new(false),
+            dither: Cell::new(Dither::default()),
+            dithering: Cell::new(false),
+            clock: Cell::new(FrameClock::new(DEFAULT_FRAME_RATE)),
+            last_written: Cell::new(None),
+            monitor: None,
         }
-        .expect("Failed to initialize RMT")
-        .into_async();
+    }
 
-        // We use one of the RMT channels to instantiate a `SmartLedsAdapterAsync` which can
-        // be used directly with all `smart_led` implementations
-        let rmt_channel = rmt.channel0;
-        let rmt_buffer = [PulseCode::default(); buffer_size_async(1)];
+    /// Renders transitions and effects at `frame_rate` frames per second.
+    pub fn with_frame_rate(self, frame_rate: u32) -> Self {
+        self.clock.set(FrameClock::new(frame_rate));
+        self
+    }
 
-        // Each devkit uses a unique GPIO for the RGB LED, so in order to support
-        // all chips we must unfortunately use `#[cfg]`s:
-        let led = { SmartLedsAdapterAsync::new(rmt_channel, pin, rmt_buffer) };
+    /// Records the rendering statistics in `monitor`.
+    pub fn with_frame_monitor(mut self, monitor: &'a FrameMonitor) -> Self {
+        self.monitor = Some(monitor);
+        self
+    }
 
-        Self {
-            led: RefCell::new(led),
-            receiver,
-            colour: Cell::new(RGB8 {
-                r: 239,
-                g: 235,
-                b: 216,
-            }),
-            level: Cell::new(150),
-            mode: Mode::ColourChanging {
-                duration: Duration::from_secs(10),
-            },
+    /// Maps levels to light output along `curve` rather than linearly.
+    pub fn with_brightness_curve(mut self, curve: BrightnessCurve) -> Self {
+        self.level_map = LevelMap::new(curve);
+        self
+    }
+
+    /// Limits the current drawn by the LEDs to `budget`, dimming frames that would exceed it.
+    pub fn with_power_budget(mut self, budget: PowerBudget) -> Self {
+        self.power_budget = Some(budget);
+        self
+    }
+
+    /// Applies the power budget to the drive values of a frame, before they are dithered.
+    fn limit_power(&self, colour: Rgb16) -> Rgb16 {
+        let Some(budget) = self.power_budget else {
+            return colour;
+        };
+
+        let (limited, is_limited) = budget.limit(colour);
+        if self.power_limited.replace(is_limited) != is_limited {
+            if is_limited {
+                warn!(
+                    "Limiting LED power: estimated {} mA exceeds budget of {} mA",
+                    budget.estimate_ua(colour) / 1000,
+                    budget.limit_ma
+                );
+            } else {
+                info!("LED power back within budget");
+            }
         }
+
+        limited
     }
 
     // Sets the LED to the current values.
-    async fn update_led(&self) -> Result<(), LedAdapterError> {
-        let colour = self.colour.get();
+    // The output is only ever borrowed here, and the driver renders one frame at a time, so the
+    // borrow held across the write never conflicts. Clippy checks this lint on the function.
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn update_led(&self) -> Result<(), O::Error> {
+        let now = Instant::now();
+        let colour = match self.effect_colour.get() {
+            Some(effect_colour) => effect_colour.value_at(now),
+            None => self.colour.get().value_at(now),
+        };
+        let output = match self.off_fade.get() {
+            Some(fade) => fade.output.value_at(now),
+            None => self.output_at(now),
+        };
+        let level = scale(output, self.effect_level.get().value_at(now) as u16 * 257);
+
+        let mut compositor = self.compositor.get();
+        let (colour, level) = compositor.frame(now).unwrap_or((colour, level));
+        self.compositor.set(compositor);
         debug!(
             "Updating LED: colour: {}, {}, {} | level: {}",
-            colour.r,
-            colour.g,
-            colour.b,
-            self.level.get()
+            colour.r, colour.g, colour.b, level
         );
 
-        #[allow(clippy::await_holding_refcell_ref)]
-        match self.led.try_borrow_mut() {
-            Ok(mut led) => {
+        match self.output.try_borrow_mut() {
+            Ok(mut output) => {
+                // Everything up to the dithering works on 16-bit linear drive values.
+                let linear = self.correction.apply(colour);
+                let linear = Rgb16::new(
+                    scale(linear.r, level),
+                    scale(linear.g, level),
+                    scale(linear.b, level),
+                );
+
+                let linear = self.limit_power(linear);
+
+                let mut dither = self.dither.get();
+                let (colour, dithering) =
+                    dither.next(linear, output.bits(), self.clock.get().frame_rate());
+                self.dither.set(dither);
+                self.dithering.set(dithering);
+
+                if self.last_written.get() == Some(colour) {
+                    return Ok(());
+                }
+
                 // This operation should be quick
-                led.write(brightness(
-                    gamma([self.colour.get()].into_iter()),
-                    self.level.get(),
-                ))
-                .await
+                let started = Instant::now();
+                let result = output.write(colour).await;
+                let elapsed = started.elapsed();
+
+                self.last_written.set(result.is_ok().then_some(colour));
+                if let Some(monitor) = self.monitor {
+                    monitor.update(|stats| {
+                        stats.writes = stats.writes.wrapping_add(1);
+                        stats.write_time = elapsed;
+                        stats.max_write_time = stats.max_write_time.max(elapsed);
+                    });
+                }
+
+                result
             }
             Err(_) => {
                 error!("unable to update LED. Skipping");
@@ -117,99 +430,506 @@ impl<'a> Driver<'a> {
         }
     }
 
+    /// The output of the level and dimmer at `now`, before effects.
+    fn output_at(&self, now: Instant) -> u16 {
+        scale(
+            self.level_map.output(self.level.get().value_at(now)),
+            self.dimmer.get().value_at(now),
+        )
+    }
+
+    /// Starts `effect` from the current output.
+    fn start_off_effect(&self, effect: OffEffect) {
+        let now = Instant::now();
+        let from = match self.off_fade.take() {
+            Some(fade) => fade.output.value_at(now),
+            None => self.output_at(now),
+        };
+
+        self.on.set(false);
+        self.dimmer.set(Transition::fixed(0));
+        self.off_fade.set(OffFade::start(effect, from));
+    }
+
+    /// Stops the running off effect, handing its current output over to the dimmer so that
+    /// the light carries on from there.
+    fn stop_off_effect(&self) {
+        let Some(fade) = self.off_fade.take() else {
+            return;
+        };
+
+        let now = Instant::now();
+        let level = self.level_map.output(self.level.get().value_at(now)).max(1);
+        let dimmer = fade.output.value_at(now) as u32 * u16::MAX as u32 / level as u32;
+        self.dimmer
+            .set(Transition::fixed(dimmer.min(u16::MAX as u32) as u16));
+    }
+
+    /// Whether the output changes from frame to frame.
+    fn is_animating(&self, now: Instant) -> bool {
+        let effect_running = self.on.get() && self.mode != Mode::Solid;
+
+        effect_running
+            || self.compositor.get().is_active()
+            || self.dithering.get()
+            || !self.dimmer.get().is_finished(now)
+            || !self.level.get().is_finished(now)
+            || !self.colour.get().is_finished(now)
+            || !self.effect_level.get().is_finished(now)
+            || self.off_fade.get().is_some()
+    }
+
+    /// Writes the current frame, retrying a few times before reporting a fault.
+    async fn write_frame(&self) {
+        for attempt in 1..=WRITE_ATTEMPTS {
+            match self.update_led().await {
+                Ok(()) => {
+                    if self.fault.take().is_some() {
+                        info!("LED writes recovered");
+                    }
+                    return;
+                }
+                Err(_) => {
+                    warn!(
+                        "Failed to write LED (attempt {}/{})",
+                        attempt, WRITE_ATTEMPTS
+                    );
+                    if attempt < WRITE_ATTEMPTS {
+                        Timer::after(WRITE_RETRY_DELAY).await;
+                    }
+                }
+            }
+        }
+
+        if self.fault.replace(Some(Fault::Write)).is_none() {
+            error!("Unable to write LED. Reporting fault");
+        }
+    }
+
+    fn publish_state(&self, now: Instant) {
+        let state = LedState {
+            on: self.on.get(),
+            level: self.level.get().target(),
+            colour: self.colour.get().target(),
+            mode: self.mode,
+            progress: Progress {
+                on_off: match self.off_fade.get() {
+                    Some(fade) => fade.remaining(now),
+                    None => self.dimmer.get().remaining(now),
+                },
+                level: self.level.get().remaining(now),
+                colour: self.colour.get().remaining(now),
+            },
+            calibration: self.correction.calibration(),
+            power_limited: self.power_limited.get(),
+            frame_rate: self.clock.get().frame_rate(),
+            fault: self.fault.get(),
+        };
+
+        if self.feedback.try_get() != Some(state) {
+            self.feedback.sender().send(state);
+        }
+    }
+
+    /// Renders frames at a fixed rate for as long as the output is changing.
+    async fn render(&self) {
+        loop {
+            // Commands restart rendering, but never ahead of the frame grid.
+            Timer::at(self.clock.get().next()).await;
+
+            let now = Instant::now();
+            let mut clock = self.clock.get();
+            let continued = clock.advance(now);
+            self.clock.set(clock);
+
+            self.write_frame().await;
+
+            let animating = self.is_animating(now);
+            let mut clock = self.clock.get();
+            clock.set_continuous(now, animating);
+            self.clock.set(clock);
+
+            if let Some(monitor) = self.monitor {
+                monitor.update(|stats| {
+                    stats.frames = stats.frames.wrapping_add(1);
+                    if let Some((frame_time, missed)) = continued {
+                        stats.frame_time = frame_time;
+                        stats.dropped = stats.dropped.wrapping_add(missed);
+                    }
+                });
+            }
+            self.publish_state(now);
+
+            if animating {
+                continue;
+            } else if self.fault.get().is_some() {
+                Timer::after(FAULT_RETRY_PERIOD).await;
+            } else {
+                // Nothing left to animate until the next command arrives.
+                core::future::pending::<()>().await;
+            }
+        }
+    }
+
     pub async fn run(mut self) -> ! {
-        self.update_led().await.unwrap();
         loop {
-            match select(self.receiver.receive(), self.run_mode()).await {
-                Either::First(command) => {
-                    match command {
-                        ControlMessage::SetOn(_on) => {
-                            // todo physically switch the LED off, i.e. cut power.
-                            // unsure if this is possible for the esp32c6.
-                        }
-                        ControlMessage::SetBrightness(level) => {
-                            self.level.set(level);
-                            self.update_led().await.unwrap();
-                        }
-                        ControlMessage::SetColour { r, g, b } => {
-                            self.colour.set(RGB8 { r, g, b });
-                            self.update_led().await.unwrap();
-                        }
-                        ControlMessage::SetMode(mode) => {
-                            warn!("Only Solid mode supported at this time");
-                            self.mode = mode;
-                        }
-                        ControlMessage::Reset => {
-                            self.colour.set(RGB8 {
-                                r: 220,
-                                g: 100,
-                                b: 20,
-                            });
-                            self.level = Cell::new(255);
-                            self.mode = Mode::Solid;
-                            self.update_led().await.unwrap();
-                        }
+            let command =
+                match select3(self.receiver.receive(), self.run_mode(), self.render()).await {
+                    Either3::First(command) => command,
+                    _ => {
+                        warn!("mode task exited unexpectedly");
+                        continue;
+                    }
+                };
+
+            match command {
+                ControlMessage::SetOn { on, transition } => {
+                    if !on && self.off_fade.get().is_some() {
+                        debug!("LED already switching off with an effect");
+                    } else {
+                        debug!("Switching LED {}", if on { "on" } else { "off" });
+                        // The colour and level are left untouched so that they are restored
+                        // when the LED is switched back on.
+                        self.stop_off_effect();
+                        self.on.set(on);
+                        let target = if on { u16::MAX } else { 0 };
+                        self.dimmer
+                            .set(self.dimmer.get().retarget(target, transition));
+                    }
+                }
+                ControlMessage::OffWithEffect(effect) => {
+                    debug!("Switching LED off with an effect");
+                    self.start_off_effect(effect);
+                }
+                ControlMessage::SetBrightness { level, transition } => {
+                    self.level.set(self.level.get().retarget(level, transition));
+                }
+                ControlMessage::SetColour {
+                    r,
+                    g,
+                    b,
+                    transition,
+                } => {
+                    self.colour
+                        .set(self.colour.get().retarget(Rgb16::new(r, g, b), transition));
+                }
+                ControlMessage::SetMode(mode) => {
+                    self.mode = mode;
+                    self.effect_level.set(Transition::fixed(u8::MAX));
+                    self.effect_colour.set(None);
+                    self.effect_step.set(0);
+                }
+                ControlMessage::SetCalibration(calibration) => {
+                    if calibration.is_valid() {
+                        self.correction = Correction::new(calibration);
+                    } else {
+                        warn!("Ignoring invalid LED calibration");
+                    }
+                }
+                ControlMessage::SetBrightnessCurve(curve) => {
+                    if curve != self.level_map.curve() {
+                        self.level_map = LevelMap::new(curve);
                     }
                 }
-                Either::Second(_) => {
-                    warn!("mode task exited unexpectedly");
+                ControlMessage::SetFrameRate(frame_rate) => {
+                    if frame_rate != self.clock.get().frame_rate() {
+                        self.clock.set(FrameClock::new(frame_rate));
+                    }
+                }
+                ControlMessage::Indicate {
+                    overlay,
+                    indication,
+                } => {
+                    let mut compositor = self.compositor.get();
+                    compositor.set(overlay, indication, Instant::now());
+                    self.compositor.set(compositor);
+                }
+                ControlMessage::FinishIndication(overlay) => {
+                    let mut compositor = self.compositor.get();
+                    compositor.finish(overlay, Instant::now());
+                    self.compositor.set(compositor);
                 }
+                ControlMessage::Reset => {
+                    self.colour
+                        .set(Transition::fixed(Rgb16::from(DEFAULT_COLOUR)));
+                    self.level.set(Transition::fixed(DEFAULT_LEVEL));
+                    self.on.set(true);
+                    self.off_fade.set(None);
+                    self.dimmer.set(Transition::fixed(u16::MAX));
+                    self.mode = DEFAULT_MODE;
+                    self.effect_level.set(Transition::fixed(u8::MAX));
+                    self.effect_colour.set(None);
+                    self.effect_step.set(0);
+                    let mut compositor = self.compositor.get();
+                    compositor.clear_below(Overlay::Status);
+                    self.compositor.set(compositor);
+                }
+            }
+
+            // Published before the next await so that the handlers never observe a state
+            // older than the commands already taken from the channel.
+            self.publish_state(Instant::now());
+        }
+    }
+
+    /// Drives the running off effect by starting a transition for each of its steps.
+    async fn run_off_effect(&self) {
+        while let Some(mut fade) = self.off_fade.get() {
+            let Some(&(percent, duration)) = fade.effect.steps().get(fade.step as usize) else {
+                self.off_fade.set(None);
+                return;
+            };
+
+            let target = OffFade::step_target(fade.from, percent);
+            // Continue the step interrupted by a command.
+            if fade.output.target() != target {
+                fade.output = fade.output.retarget(target, duration);
+                self.off_fade.set(Some(fade));
             }
+
+            Timer::at(fade.output.end()).await;
+            fade.step += 1;
+            self.off_fade.set(Some(fade));
         }
     }
 
+    /// Drives the effect of the current mode by starting a transition for each of its steps.
     async fn run_mode(&self) {
+        // Effects are paused while the LED is off and resume once it is switched back on.
+        if !self.on.get() {
+            self.run_off_effect().await;
+            return core::future::pending::<()>().await;
+        }
+
         match self.mode {
             Mode::Solid => core::future::pending::<()>().await,
             Mode::Pulse { duration } => {
                 // Limit minimum to 500 milliseconds
                 let duration = duration.max(Duration::from_millis(500));
 
-                let max_level = self.level.get();
-                let mut direction_up = true;
-
                 loop {
-                    match direction_up {
-                        true => {
-                            self.level.set(self.level.get().saturating_add(1));
-                            if self.level.get() >= max_level {
-                                direction_up = false;
-                            }
-                        }
-                        false => {
-                            self.level.set(self.level.get().saturating_sub(1));
-                            if self.level.get() <= 1 {
-                                direction_up = true;
-                            }
-                        }
-                    }
-                    self.update_led().await.unwrap();
-                    Timer::after(duration.checked_div(max_level as u32).unwrap()).await
+                    // Even steps dim down, odd steps brighten back up.
+                    let target = if self.effect_step.get().is_multiple_of(2) {
+                        PULSE_MIN
+                    } else {
+                        u8::MAX
+                    };
+
+                    let transition = self.effect_level.get();
+                    let transition = if transition.target() == target {
+                        // Continue the step interrupted by a command.
+                        transition
+                    } else {
+                        transition.retarget(target, duration)
+                    };
+                    self.effect_level.set(transition);
+
+                    Timer::at(transition.end()).await;
+                    self.effect_step.set(self.effect_step.get().wrapping_add(1));
                 }
             }
             Mode::ColourChanging { duration } => {
                 // Limit minimum to 500 milliseconds
                 let duration = duration.max(Duration::from_millis(500));
-
-                // todo I don't like this colour transition. It spends too much time on the primary colours.
-                // Consider alternative algorithms.
-                let mut hue: u8 = 0;
+                let segment = duration / HUE_WHEEL.len() as u32;
 
                 loop {
-                    hue = hue.wrapping_add(1);
+                    let step = self.effect_step.get() as usize % HUE_WHEEL.len();
+                    let target = HUE_WHEEL[step];
 
-                    let hue_colour = Hsv {
-                        hue,
-                        sat: 255,
-                        val: 255,
+                    let transition = match self.effect_colour.get() {
+                        // Continue the step interrupted by a command.
+                        Some(transition) if transition.target() == target => transition,
+                        Some(transition) => transition.retarget(target, segment),
+                        None => self.colour.get().retarget(target, segment),
                     };
+                    self.effect_colour.set(Some(transition));
 
-                    self.colour.set(hsv2rgb(hue_colour));
-                    self.update_led().await.unwrap();
-
-                    Timer::after(duration.checked_div(255).unwrap()).await;
+                    Timer::at(transition.end()).await;
+                    self.effect_step.set(((step + 1) % HUE_WHEEL.len()) as u8);
                 }
             }
         }
     }
 }
+
+/// Scales `value` by `factor`, where a `factor` of `u16::MAX` leaves `value` unchanged.
+fn scale(value: u16, factor: u16) -> u16 {
+    ((value as u32 * factor as u32 + 0x7fff) / u16::MAX as u32) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::led::led_channel::LedChannel;
+
+    /// An output whose first `failures` writes fail.
+    struct FlakyOutput {
+        failures: u8,
+        writes: u8,
+    }
+
+    impl FlakyOutput {
+        fn new(failures: u8) -> Self {
+            Self {
+                failures,
+                writes: 0,
+            }
+        }
+    }
+
+    impl LedOutput for FlakyOutput {
+        type Error = ();
+
+        async fn write(&mut self, _colour: Rgb16) -> Result<(), Self::Error> {
+            self.writes += 1;
+            if self.writes <= self.failures {
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn write_is_retried_before_reporting_a_fault() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let driver = Driver::new(
+            FlakyOutput::new(WRITE_ATTEMPTS - 1),
+            Calibration::default(),
+            channel.receiver(),
+            &feedback,
+        );
+
+        block_on(driver.write_frame());
+        assert_eq!(driver.output.borrow().writes, WRITE_ATTEMPTS);
+        assert_eq!(driver.fault.get(), None);
+    }
+
+    #[test]
+    fn repeated_write_failures_report_a_fault() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let driver = Driver::new(
+            FlakyOutput::new(WRITE_ATTEMPTS + 1),
+            Calibration::default(),
+            channel.receiver(),
+            &feedback,
+        );
+
+        block_on(driver.write_frame());
+        assert_eq!(driver.output.borrow().writes, WRITE_ATTEMPTS);
+        assert_eq!(driver.fault.get(), Some(Fault::Write));
+
+        driver.publish_state(Instant::now());
+        assert_eq!(feedback.try_get().unwrap().fault, Some(Fault::Write));
+
+        // The next frame written clears the fault.
+        block_on(driver.write_frame());
+        assert_eq!(driver.output.borrow().writes, WRITE_ATTEMPTS + 2);
+        assert_eq!(driver.fault.get(), None);
+    }
+
+    fn driver<'a>(channel: &'a LedChannel, feedback: &'a LedFeedback) -> Driver<'a, FlakyOutput> {
+        Driver::new(
+            FlakyOutput::new(0),
+            Calibration::default(),
+            channel.receiver(),
+            feedback,
+        )
+    }
+
+    #[test]
+    fn off_effects_take_the_sum_of_their_steps() {
+        assert_eq!(OffEffect::Fade.duration(), Duration::from_millis(800));
+        assert_eq!(OffEffect::NoFade.duration(), Duration::from_ticks(0));
+        assert_eq!(
+            OffEffect::DimThenFade.duration(),
+            Duration::from_millis(12_800)
+        );
+        assert_eq!(
+            OffEffect::DyingLight.duration(),
+            Duration::from_millis(1500)
+        );
+
+        for effect in [
+            OffEffect::Fade,
+            OffEffect::DimThenFade,
+            OffEffect::DyingLight,
+        ] {
+            assert_eq!(effect.steps().last().unwrap().0, 0, "{effect:?}");
+        }
+    }
+
+    #[test]
+    fn off_effect_progress_covers_every_step() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let driver = driver(&channel, &feedback);
+
+        driver.start_off_effect(OffEffect::DimThenFade);
+        assert!(!driver.on.get());
+
+        let fade = driver.off_fade.get().unwrap();
+        assert_eq!(fade.output.target(), fade.from / 2);
+
+        let started = fade.output.end() - Duration::from_millis(800);
+        assert_eq!(fade.remaining(started), OffEffect::DimThenFade.duration());
+        assert_eq!(
+            fade.remaining(started + Duration::from_millis(300)),
+            Duration::from_millis(12_500)
+        );
+        assert_eq!(fade.remaining(fade.output.end()), Duration::from_secs(12));
+
+        let last = OffFade {
+            step: 1,
+            output: Transition::fixed(0),
+            ..fade
+        };
+        assert_eq!(last.remaining(started), Duration::from_ticks(0));
+    }
+
+    #[test]
+    fn off_effect_steps_are_relative_to_the_starting_output() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let driver = driver(&channel, &feedback);
+
+        // Brightening is capped at full output.
+        driver.start_off_effect(OffEffect::DyingLight);
+        let fade = driver.off_fade.get().unwrap();
+        assert_eq!(
+            fade.output.target(),
+            (fade.from as u32 * 6 / 5).min(u16::MAX as u32) as u16
+        );
+        assert_eq!(OffFade::step_target(1000, 120), 1200);
+        assert_eq!(OffFade::step_target(u16::MAX, 120), u16::MAX);
+        assert_eq!(OffFade::step_target(1000, 50), 500);
+
+        // An effect without steps switches off at once.
+        driver.start_off_effect(OffEffect::NoFade);
+        assert_eq!(driver.off_fade.get(), None);
+        assert!(!driver.on.get());
+    }
+
+    #[test]
+    fn off_effect_ends_after_its_last_step() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let driver = driver(&channel, &feedback);
+
+        // A fade whose only step has already reached its target.
+        driver.off_fade.set(Some(OffFade {
+            effect: OffEffect::Fade,
+            from: 1000,
+            step: 0,
+            output: Transition::fixed(0),
+        }));
+
+        block_on(driver.run_off_effect());
+        assert_eq!(driver.off_fade.get(), None);
+    }
+}
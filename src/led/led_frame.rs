@@ -0,0 +1,199 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
+
+/// Frame rate used unless the driver is configured otherwise.
+pub const DEFAULT_FRAME_RATE: u32 = 50;
+
+/// Schedules frames on a fixed grid, so that animations advance at an even rate no matter when
+/// commands arrive.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameClock {
+    frame_rate: u32,
+    period: Duration,
+    /// Earliest time at which the next frame may be rendered.
+    next: Instant,
+    /// When the previous frame was rendered, if it was part of an ongoing animation.
+    previous: Option<Instant>,
+}
+
+impl FrameClock {
+    pub fn new(frame_rate: u32) -> Self {
+        let frame_rate = frame_rate.max(1);
+        Self {
+            frame_rate,
+            period: Duration::from_hz(frame_rate as u64),
+            next: Instant::from_ticks(0),
+            previous: None,
+        }
+    }
+
+    pub fn frame_rate(&self) -> u32 {
+        self.frame_rate
+    }
+
+    pub fn next(&self) -> Instant {
+        self.next
+    }
+
+    /// Advances past the frame rendered at `now`. Returns the time since the previous frame and
+    /// the number of frame slots missed since, if the frame continues an animation.
+    pub fn advance(&mut self, now: Instant) -> Option<(Duration, u32)> {
+        let Some(previous) = self.previous else {
+            // Nothing ran before this frame, so start a new grid.
+            self.next = now + self.period;
+            return None;
+        };
+
+        let late = now.saturating_duration_since(self.next);
+        let missed = (late.as_ticks() / self.period.as_ticks()) as u32;
+        self.next += self.period * (missed + 1);
+
+        Some((now - previous, missed))
+    }
+
+    /// Records whether the frame rendered at `now` is followed by more frames of an animation.
+    pub fn set_continuous(&mut self, now: Instant, continuous: bool) {
+        self.previous = continuous.then_some(now);
+    }
+}
+
+/// Rendering statistics of the driver.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    /// Frames rendered.
+    pub frames: u32,
+    /// Frames written to the LED. Frames identical to the previous one are not written again.
+    pub writes: u32,
+    /// Frame slots missed while animating, because rendering fell behind.
+    pub dropped: u32,
+    /// Time between the last two frames of an animation.
+    pub frame_time: Duration,
+    /// Time taken by the last write to the LED.
+    pub write_time: Duration,
+    /// Longest time taken by a write to the LED.
+    pub max_write_time: Duration,
+}
+
+/// Collects the [`FrameStats`] of a driver so that they can be queried while it runs.
+pub struct FrameMonitor {
+    stats: Mutex<CriticalSectionRawMutex, Cell<FrameStats>>,
+}
+
+impl FrameMonitor {
+    pub const fn new() -> Self {
+        Self {
+            stats: Mutex::new(Cell::new(FrameStats {
+                frames: 0,
+                writes: 0,
+                dropped: 0,
+                frame_time: Duration::from_ticks(0),
+                write_time: Duration::from_ticks(0),
+                max_write_time: Duration::from_ticks(0),
+            })),
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats.lock(|stats| stats.get())
+    }
+
+    /// Clears the statistics, e.g. to measure over a fresh interval.
+    pub fn reset(&self) {
+        self.stats.lock(|stats| stats.set(FrameStats::default()));
+    }
+
+    pub(crate) fn update(&self, f: impl FnOnce(&mut FrameStats)) {
+        self.stats.lock(|stats| {
+            let mut value = stats.get();
+            f(&mut value);
+            stats.set(value);
+        });
+    }
+}
+
+impl Default for FrameMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::at;
+
+    const PERIOD: Duration = Duration::from_millis(20);
+
+    /// A clock at 50 frames per second whose animation started with a frame at `start`.
+    fn animating(start: u64) -> FrameClock {
+        let mut clock = FrameClock::new(50);
+        assert_eq!(clock.advance(at(start)), None);
+        clock.set_continuous(at(start), true);
+        clock
+    }
+
+    #[test]
+    fn first_frame_starts_the_grid() {
+        let mut clock = FrameClock::new(50);
+        assert_eq!(clock.advance(at(1005)), None);
+        assert_eq!(clock.next(), at(1025));
+    }
+
+    #[test]
+    fn frames_on_time_drop_nothing() {
+        let mut clock = animating(1000);
+        assert_eq!(clock.advance(at(1020)), Some((PERIOD, 0)));
+        clock.set_continuous(at(1020), true);
+
+        // A frame late by less than a period still keeps to the grid.
+        assert_eq!(
+            clock.advance(at(1055)),
+            Some((Duration::from_millis(35), 0))
+        );
+        assert_eq!(clock.next(), at(1060));
+    }
+
+    #[test]
+    fn late_frames_count_the_missed_slots() {
+        let mut clock = animating(1000);
+        // The slots at 1020, 1040 and 1060 passed, this frame takes the one at 1080.
+        assert_eq!(
+            clock.advance(at(1085)),
+            Some((Duration::from_millis(85), 3))
+        );
+        assert_eq!(clock.next(), at(1100));
+    }
+
+    #[test]
+    fn idle_time_is_not_counted_as_dropped() {
+        let mut clock = animating(1000);
+        clock.set_continuous(at(1000), false);
+
+        // The next command arrives long after the animation ended.
+        assert_eq!(clock.advance(at(5003)), None);
+        assert_eq!(clock.next(), at(5023));
+    }
+
+    #[test]
+    fn frame_rate_is_at_least_one() {
+        let clock = FrameClock::new(0);
+        assert_eq!(clock.frame_rate(), 1);
+        assert_eq!(clock.period, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn monitor_resets_the_statistics() {
+        let monitor = FrameMonitor::new();
+        monitor.update(|stats| {
+            stats.frames = 3;
+            stats.dropped = 1;
+        });
+        assert_eq!(monitor.stats().dropped, 1);
+
+        monitor.reset();
+        assert_eq!(monitor.stats(), FrameStats::default());
+    }
+}
@@ -8,60 +8,400 @@ use log::{debug, error};
 
 use rs_matter::dm::clusters::level_control::OptionsBitmap;
 use rs_matter_embassy::matter::dm::Cluster;
-use rs_matter_embassy::matter::dm::clusters::level_control::{self, LevelControlHooks};
+use rs_matter_embassy::matter::dm::clusters::identify::{
+    EffectIdentifierEnum, IdentifyAction, IdentifyHooks, IdentifyTypeEnum,
+};
+use rs_matter_embassy::matter::dm::clusters::level_control::{
+    self, LevelControlHandler, LevelControlHooks,
+};
 use rs_matter_embassy::matter::dm::clusters::on_off::{self, OnOffHooks, StartUpOnOffEnum};
 use rs_matter_embassy::matter::error::{Error, ErrorCode};
 use rs_matter_embassy::matter::tlv::Nullable;
 use rs_matter_embassy::matter::with;
 
-use crate::led::led_driver::{ControlMessage, LedSender};
+use crate::led::led_attributes::{AttributesSignal, LightAttributes};
+use crate::led::led_calibration::Calibration;
+use crate::led::led_channel::LedSender;
+use crate::led::led_compositor::Overlay;
+use crate::led::led_curve::{BrightnessCurve, CurveSignal};
+use crate::led::led_driver::{ControlMessage, LedFeedback, LedState, OffEffect};
+use crate::led::led_frame::{DEFAULT_FRAME_RATE, FrameMonitor, FrameStats};
 
-use esp_hal::Blocking;
-use esp_hal::analog::adc::{Adc, AdcPin};
-use esp_hal::gpio::Input;
-use esp_hal::peripherals::{ADC1, GPIO4};
+use embassy_futures::join::join;
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer};
 
-use embassy_time::Timer;
-
-use crate::dm::color_control::ColorControlHooks;
-use palette::white_point::D65;
-use palette::{FromColor, Srgb, Yxy};
+use crate::dm::color_control::{self, ColorControlHooks};
+use crate::dm::lamp_maintenance::LampMaintenanceHooks;
+use crate::dm::lamp_notification::{FlashLength, LampNotificationHooks};
+use crate::input::input_analog::AnalogInput;
+use crate::input::input_button::Button;
+use crate::input::input_encoder::{EncoderMode, EncoderTarget};
+use crate::input::input_filter::{AnalogFilter, FilterConfig};
+use crate::input::input_gesture::{
+    ButtonAction, Gesture, GestureConfig, GestureMap, GestureRecognizer,
+};
+use crate::input::input_range::{InputRange, RangeSignal, RangeTracker, RangeUpdate};
+use crate::input::input_takeover::SoftTakeover;
+use crate::input::input_throttle::Throttle;
+use crate::led::led_colour::{Rgb16, mireds_to_rgb, xy_to_rgb};
+use crate::led::led_indication::{Indication, Repeat};
+use crate::led::led_status::StatusIndicator;
+use crate::reset::Resettable;
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub struct LedHandler<'a> {
+pub struct LedHandler<'a, B: Button, A: AnalogInput> {
     sender: LedSender<'a>,
-    button_on_off: RefCell<Input<'a>>,
-    adc: RefCell<Adc<'a, ADC1<'a>, Blocking>>,
-    pin: RefCell<AdcPin<GPIO4<'a>, ADC1<'a>>>, // concrete types used to simplify example
+    feedback: &'a LedFeedback,
+    button_on_off: RefCell<GestureRecognizer<B>>,
+    gestures: Cell<GestureMap>,
+    knob: RefCell<A>,
+    knob_filter: Cell<FilterConfig>,
+    knob_range: Cell<InputRange>,
+    knob_range_updates: RangeSignal,
+    calibrate_knob: Cell<bool>,
+    reset_knob: Cell<bool>,
+    // The level last reported by the knob, and whether another input changed it since
+    knob_level: Cell<Option<u8>>,
+    knob_released: Cell<bool>,
+    // The LED calibration last requested, which the feedback may not reflect yet
+    calibration: Cell<Option<Calibration>>,
+    curve_updates: CurveSignal,
+    frame_monitor: Option<&'a FrameMonitor>,
+    status_indicator: Option<&'a StatusIndicator<'a>>,
+    // The frame rate last requested, which the feedback may not reflect yet
+    frame_rate: Cell<Option<u8>>,
+    // Messages requested by the inputs, sent from `LevelControlHooks::run` and
+    // `ColorControlHooks::run`
+    level_requests: Channel<CriticalSectionRawMutex, level_control::OutOfBandMessage, 8>,
+    colour_requests: Channel<CriticalSectionRawMutex, color_control::OutOfBandMessage, 8>,
+    dim_up: Cell<bool>,
+    dimming: Cell<bool>,
+    attribute_updates: AttributesSignal,
     // OnOff Attributes
     on_off: Cell<bool>,
     start_up_on_off: Cell<Option<StartUpOnOffEnum>>,
     // LevelControl Attributes
     current_level: Cell<Option<u8>>,
     startup_current_level: Cell<Option<u8>>,
+    on_off_transitions: Cell<Option<&'a dyn OnOffTransitions>>,
+}
+
+/// The LevelControl attributes setting how long the light takes to switch on and off, in
+/// tenths of a second.
+pub trait OnOffTransitions {
+    /// `OnTransitionTime`, or `None` if null.
+    fn on_transition_time(&self) -> Option<u16>;
+
+    /// `OffTransitionTime`, or `None` if null.
+    fn off_transition_time(&self) -> Option<u16>;
+
+    /// `OnOffTransitionTime`, used when the above are null.
+    fn on_off_transition_time(&self) -> u16;
 }
 
-impl<'a> LedHandler<'a> {
+impl<H: LevelControlHooks, OH: OnOffHooks> OnOffTransitions for LevelControlHandler<'_, H, OH> {
+    fn on_transition_time(&self) -> Option<u16> {
+        LevelControlHandler::on_transition_time(self).into_option()
+    }
+
+    fn off_transition_time(&self) -> Option<u16> {
+        LevelControlHandler::off_transition_time(self).into_option()
+    }
+
+    fn on_off_transition_time(&self) -> u16 {
+        LevelControlHandler::on_off_transition_time(self)
+    }
+}
+
+impl<'a, B: Button, A: AnalogInput> LedHandler<'a, B, A> {
+    /// The LevelControl `OnOffTransitionTime` until a controller sets one, in tenths of a second.
+    pub const DEFAULT_ON_OFF_TRANSITION_TIME: u16 = 5;
+
+    /// Time over which the driver smooths each level update, hiding the step interval of
+    /// LevelControl transitions and the potentiometer.
+    const LEVEL_SMOOTHING: Duration = Duration::from_millis(100);
+
+    /// Rate at which the level moves while the button is held, in levels per second.
+    const HOLD_DIM_RATE: u8 = 85;
+
+    /// Levels stepped for each detent turned on an encoder.
+    const ENCODER_LEVEL_STEP: u32 = 8;
+
+    /// Degrees of hue stepped for each detent turned on an encoder.
+    const ENCODER_HUE_STEP: i32 = 10;
+
+    /// Mireds stepped for each detent turned on an encoder.
+    const ENCODER_MIREDS_STEP: i32 = 10;
+
+    /// The end stops of the reference potentiometer at 11 dB attenuation, used until it is
+    /// calibrated. Obtained empirically.
+    pub const DEFAULT_KNOB_RANGE: InputRange = InputRange::new(2300, 4081);
+
+    /// Levels the knob has to be turned by to take over the level after it was changed by other
+    /// means, if it was not turned past the level first.
+    const KNOB_TAKEOVER_THRESHOLD: u8 = 64;
+
+    /// Levels the knob has to be turned by to switch the light on.
+    const KNOB_SWITCH_ON_THRESHOLD: u32 = 5;
+
+    /// Shortest interval between the knob's level updates to the LevelControl state, as each
+    /// one is reported to every subscriber. The LED follows the knob straight away.
+    const KNOB_REPORT_INTERVAL: Duration = Duration::from_millis(300);
+
     pub fn new(
         sender: LedSender<'a>,
-        button_on_off: Input<'a>,
-        adc: Adc<'a, ADC1<'a>, Blocking>,
-        pin: AdcPin<GPIO4<'a>, ADC1<'a>>,
+        feedback: &'a LedFeedback,
+        button_on_off: B,
+        knob: A,
     ) -> Self {
         Self {
             sender,
-            button_on_off: RefCell::new(button_on_off),
-            adc: RefCell::new(adc),
-            pin: RefCell::new(pin),
-            on_off: Cell::new(true),
-            start_up_on_off: Cell::new(None),
-            current_level: Cell::new(Some(42)),
-            startup_current_level: Cell::new(None),
+            feedback,
+            button_on_off: RefCell::new(GestureRecognizer::new(
+                button_on_off,
+                GestureConfig::default(),
+            )),
+            gestures: Cell::new(GestureMap::default()),
+            knob: RefCell::new(knob),
+            knob_filter: Cell::new(FilterConfig::default()),
+            knob_range: Cell::new(Self::DEFAULT_KNOB_RANGE),
+            knob_range_updates: RangeSignal::new(),
+            calibrate_knob: Cell::new(false),
+            reset_knob: Cell::new(false),
+            knob_level: Cell::new(None),
+            knob_released: Cell::new(false),
+            calibration: Cell::new(None),
+            curve_updates: CurveSignal::new(),
+            frame_monitor: None,
+            status_indicator: None,
+            frame_rate: Cell::new(None),
+            level_requests: Channel::new(),
+            colour_requests: Channel::new(),
+            dim_up: Cell::new(false),
+            dimming: Cell::new(false),
+            attribute_updates: AttributesSignal::new(),
+            on_off: Cell::new(LightAttributes::DEFAULT.on_off),
+            start_up_on_off: Cell::new(LightAttributes::DEFAULT.start_up_on_off),
+            current_level: Cell::new(LightAttributes::DEFAULT.current_level),
+            startup_current_level: Cell::new(LightAttributes::DEFAULT.start_up_current_level),
+            on_off_transitions: Cell::new(None),
+        }
+    }
+
+    /// Restores the attributes saved before the last power loss, and shows them on the LED.
+    ///
+    /// The `StartUpOnOff` and `StartUpCurrentLevel` behaviour is left to the `init` of the OnOff
+    /// and LevelControl cluster handlers, which apply it through the hooks, so that it is applied
+    /// once and the outcome, e.g. of a toggle, is saved like any other change.
+    pub fn with_attributes(self, saved: LightAttributes) -> Self {
+        debug!(
+            "Restoring {} at level {}",
+            if saved.on_off { "on" } else { "off" },
+            saved.current_level.unwrap_or(Self::MIN_LEVEL)
+        );
+
+        self.on_off.set(saved.on_off);
+        self.start_up_on_off.set(saved.start_up_on_off);
+        self.current_level.set(saved.current_level);
+        self.startup_current_level.set(saved.start_up_current_level);
+
+        self.sender.send(ControlMessage::SetOn {
+            on: saved.on_off,
+            transition: Duration::from_ticks(0),
+        });
+        if let Some(level) = saved.current_level {
+            self.sender.send(ControlMessage::SetBrightness {
+                level,
+                transition: Duration::from_ticks(0),
+            });
+        }
+        self
+    }
+
+    /// Sets the timings used to recognise gestures on the on/off button.
+    pub fn with_gesture_config(self, config: GestureConfig) -> Self {
+        self.button_on_off.borrow_mut().set_config(config);
+        self
+    }
+
+    /// Sets the actions performed for each gesture on the on/off button.
+    pub fn with_gesture_map(self, map: GestureMap) -> Self {
+        self.gestures.set(map);
+        self
+    }
+
+    /// Sets how the readings of the potentiometer are filtered.
+    pub fn with_knob_filter(self, config: FilterConfig) -> Self {
+        self.knob_filter.set(config);
+        self
+    }
+
+    /// Sets the end stops of the potentiometer, e.g. as saved by a previous calibration.
+    pub fn with_knob_range(self, range: InputRange) -> Self {
+        self.knob_range.set(range);
+        self
+    }
+
+    /// Signals each potentiometer range that should be saved, after a calibration or once the
+    /// end stops drifted.
+    pub fn knob_range_updates(&self) -> &RangeSignal {
+        &self.knob_range_updates
+    }
+
+    /// Reports the rendering statistics the LED driver records in `monitor`.
+    pub fn with_frame_monitor(mut self, monitor: &'a FrameMonitor) -> Self {
+        self.frame_monitor = Some(monitor);
+        self
+    }
+
+    /// Lets controllers hide or show the status patterns of `indicator`.
+    pub fn with_status_indicator(mut self, indicator: &'a StatusIndicator<'a>) -> Self {
+        self.status_indicator = Some(indicator);
+        self
+    }
+
+    /// Signals each brightness curve set by a controller, to be saved.
+    pub fn curve_updates(&self) -> &CurveSignal {
+        &self.curve_updates
+    }
+
+    /// Signals the OnOff and LevelControl attributes whenever they change, to be saved for the
+    /// next start-up.
+    pub fn attribute_updates(&self) -> &AttributesSignal {
+        &self.attribute_updates
+    }
+
+    fn attributes(&self) -> LightAttributes {
+        LightAttributes {
+            on_off: self.on_off.get(),
+            start_up_on_off: self.start_up_on_off.get(),
+            current_level: self.current_level.get(),
+            start_up_current_level: self.startup_current_level.get(),
+        }
+    }
+
+    /// Fades the LED on and off over the transition times of `transitions`, the LevelControl
+    /// handler, which is created after this handler.
+    pub fn set_on_off_transitions(&self, transitions: &'a dyn OnOffTransitions) {
+        self.on_off_transitions.set(Some(transitions));
+    }
+
+    /// The time the LED takes to switch `on` or off, in tenths of a second: the LevelControl
+    /// `OnTransitionTime` or `OffTransitionTime`, else its `OnOffTransitionTime`.
+    fn on_off_transition_time(&self, on: bool) -> u16 {
+        let Some(transitions) = self.on_off_transitions.get() else {
+            return Self::DEFAULT_ON_OFF_TRANSITION_TIME;
+        };
+
+        let time = if on {
+            transitions.on_transition_time()
+        } else {
+            transitions.off_transition_time()
+        };
+        time.unwrap_or_else(|| transitions.on_off_transition_time())
+    }
+
+    fn attributes_changed(&self) {
+        self.attribute_updates.signal(self.attributes());
+    }
+
+    /// Starts learning the end stops of the potentiometer, which has to be turned from end to
+    /// end within the next few seconds.
+    pub fn start_knob_calibration(&self) {
+        self.calibrate_knob.set(true);
+    }
+
+    /// The state last applied by the LED driver.
+    pub fn led_state(&self) -> Option<LedState> {
+        self.feedback.try_get()
+    }
+
+    /// The driver state to reflect in the Matter attributes, if it deviates from what the
+    /// handlers requested on their own, e.g. after the driver was reset.
+    ///
+    /// Returns `None` while commands are still on their way to the driver, as the feedback
+    /// does not reflect them yet, and while the driver is faulted.
+    fn reconcilable_state(&self, state: LedState) -> Option<LedState> {
+        (self.sender.is_idle() && state.fault.is_none()).then_some(state)
+    }
+
+    fn is_faulted(&self) -> bool {
+        self.led_state().is_some_and(|state| state.fault.is_some())
+    }
+
+    /// Performs the action mapped to `gesture` on the on/off button.
+    fn handle_gesture(&self, gesture: Gesture, notify: &impl Fn(on_off::OutOfBandMessage)) {
+        if gesture == Gesture::Release && self.dimming.replace(false) {
+            self.request_level(level_control::OutOfBandMessage::Stop {
+                with_on_off: false,
+                options_mask: OptionsBitmap::default(),
+                options_override: OptionsBitmap::default(),
+            });
+            return;
+        }
+
+        match self.gestures.get().action(gesture) {
+            ButtonAction::None => {}
+            ButtonAction::Toggle => notify(on_off::OutOfBandMessage::Toggle),
+            ButtonAction::On => notify(on_off::OutOfBandMessage::On),
+            ButtonAction::Off => notify(on_off::OutOfBandMessage::Off),
+            ButtonAction::CalibrateKnob => self.start_knob_calibration(),
+            ButtonAction::Level(level) => {
+                self.request_level(level_control::OutOfBandMessage::MoveToLevel {
+                    with_on_off: true,
+                    level,
+                    transition_time: None,
+                    options_mask: OptionsBitmap::default(),
+                    options_override: OptionsBitmap::default(),
+                })
+            }
+            ButtonAction::Dim if gesture == Gesture::Hold => {
+                // Like a wall dimmer: brighten from off or the minimum, dim from the maximum,
+                // and otherwise reverse the direction of the last hold.
+                let up = match self.current_level() {
+                    _ if !self.on_off() => true,
+                    Some(level) if level >= Self::MAX_LEVEL => false,
+                    Some(level) if level <= Self::MIN_LEVEL => true,
+                    _ => !self.dim_up.get(),
+                };
+                debug!("Dimming {}", if up { "up" } else { "down" });
+
+                self.dim_up.set(up);
+                self.dimming.set(true);
+                // Only switch on when brightening, so dimming down stops at the minimum level.
+                self.request_level(level_control::OutOfBandMessage::Move {
+                    with_on_off: up,
+                    move_mode: if up {
+                        level_control::MoveModeEnum::Up
+                    } else {
+                        level_control::MoveModeEnum::Down
+                    },
+                    rate: Some(Self::HOLD_DIM_RATE),
+                    options_mask: OptionsBitmap::default(),
+                    options_override: OptionsBitmap::default(),
+                });
+            }
+            ButtonAction::Dim => {}
+        }
+    }
+
+    fn request_level(&self, message: level_control::OutOfBandMessage) {
+        if self.level_requests.try_send(message).is_err() {
+            error!("Too many level requests. Input dropped");
+        }
+    }
+
+    fn request_colour(&self, message: color_control::OutOfBandMessage) {
+        if self.colour_requests.try_send(message).is_err() {
+            error!("Too many colour requests. Input dropped");
         }
     }
 }
 
-impl<'a> OnOffHooks for LedHandler<'a> {
+impl<'a, B: Button, A: AnalogInput> OnOffHooks for LedHandler<'a, B, A> {
     const CLUSTER: Cluster<'static> = on_off::FULL_CLUSTER
         .with_revision(6)
         .with_features(on_off::Feature::LIGHTING.bits())
@@ -86,10 +426,11 @@ impl<'a> OnOffHooks for LedHandler<'a> {
         self.on_off.get()
     }
 
-    // todo this method should probably return an error `.map_err(|_| Error::new(ErrorCode::Busy))`
     fn set_on_off(&self, on: bool) {
-        let _ = self.sender.try_send(ControlMessage::SetOn(on));
+        let transition = Duration::from_millis(self.on_off_transition_time(on) as u64 * 100);
+        self.sender.send(ControlMessage::SetOn { on, transition });
         self.on_off.set(on);
+        self.attributes_changed();
         debug!("OnOff state set to: {}", on);
     }
 
@@ -102,33 +443,72 @@ impl<'a> OnOffHooks for LedHandler<'a> {
 
     fn set_start_up_on_off(&self, value: Nullable<on_off::StartUpOnOffEnum>) -> Result<(), Error> {
         self.start_up_on_off.set(value.into_option());
+        self.attributes_changed();
         Ok(())
     }
 
-    async fn handle_off_with_effect(&self, _effect: on_off::EffectVariantEnum) {
-        // no effect
+    async fn handle_off_with_effect(&self, effect: on_off::EffectVariantEnum) {
+        let effect = match effect {
+            on_off::EffectVariantEnum::DelayedAllOff(variant) => match variant {
+                on_off::DelayedAllOffEffectVariantEnum::DelayedOffFastFade => OffEffect::Fade,
+                on_off::DelayedAllOffEffectVariantEnum::NoFade => OffEffect::NoFade,
+                on_off::DelayedAllOffEffectVariantEnum::DelayedOffSlowFade => {
+                    OffEffect::DimThenFade
+                }
+            },
+            on_off::EffectVariantEnum::DyingLight(_) => OffEffect::DyingLight,
+        };
+
+        // Updated first so that the driver switching off is not reported back as a change.
+        self.on_off.set(false);
+        self.attributes_changed();
+        self.sender.send(ControlMessage::OffWithEffect(effect));
+        debug!("OnOff state set to: false, with an effect");
+
+        // The cluster switches off once this returns, by which time the effect has finished.
+        Timer::after(effect.duration()).await;
     }
 
     async fn run<F: Fn(on_off::OutOfBandMessage)>(&self, notify: F) {
         // This should never panic since button_on_off is only accessed here.
         #![allow(clippy::await_holding_refcell_ref)]
-        let mut button_ref = self.button_on_off.borrow_mut();
-        loop {
-            button_ref.wait_for_any_edge().await;
-            if button_ref.is_low() {
-                notify(on_off::OutOfBandMessage::Toggle);
-
-                // Debounce delay
-                Timer::after_millis(50).await;
-            } else {
-                // Debounce delay
-                Timer::after_millis(50).await;
+        let mut feedback = self
+            .feedback
+            .receiver()
+            .expect("More LED feedback consumers than LED_FEEDBACK_RECEIVERS");
+
+        // The button is read in a future of its own, as the driver publishes its state on every
+        // frame of a transition, which would otherwise restart the debouncing of each press.
+        let button = async {
+            let mut gestures = self.button_on_off.borrow_mut();
+            loop {
+                match gestures.next().await {
+                    Err(_) => error!("Error reading button"),
+                    Ok(gesture) => self.handle_gesture(gesture, &notify),
+                }
             }
-        }
+        };
+
+        let driver = async {
+            loop {
+                if let Some(state) = self.reconcilable_state(feedback.changed().await)
+                    && state.on != self.on_off()
+                {
+                    debug!("LED switched {} by the driver", state.on);
+                    notify(if state.on {
+                        on_off::OutOfBandMessage::On
+                    } else {
+                        on_off::OutOfBandMessage::Off
+                    });
+                }
+            }
+        };
+
+        join(button, driver).await;
     }
 }
 
-impl<'a> LevelControlHooks for LedHandler<'a> {
+impl<'a, B: Button, A: AnalogInput> LevelControlHooks for LedHandler<'a, B, A> {
     const MIN_LEVEL: u8 = 1;
 
     const MAX_LEVEL: u8 = 254;
@@ -166,9 +546,19 @@ impl<'a> LevelControlHooks for LedHandler<'a> {
 
     fn set_device_level(&self, level: u8) -> Result<Option<u8>, ()> {
         debug!("LedHandler::set_device_level: level {}", level);
-        self.sender
-            .try_send(ControlMessage::SetBrightness(level))
-            .map_err(|_| ())?;
+        if self.is_faulted() {
+            error!("LED is faulted. Level not applied");
+            return Err(());
+        }
+
+        if self.knob_level.get() != Some(level) {
+            self.knob_released.set(true);
+        }
+
+        self.sender.send(ControlMessage::SetBrightness {
+            level,
+            transition: Self::LEVEL_SMOOTHING,
+        });
         Ok(Some(level))
     }
 
@@ -178,7 +568,8 @@ impl<'a> LevelControlHooks for LedHandler<'a> {
 
     fn set_current_level(&self, level: Option<u8>) {
         debug!("LedHandler::set_current_level: level {:?}", level);
-        self.current_level.set(level)
+        self.current_level.set(level);
+        self.attributes_changed();
     }
 
     fn start_up_current_level(&self) -> Result<Option<u8>, Error> {
@@ -187,86 +578,771 @@ impl<'a> LevelControlHooks for LedHandler<'a> {
 
     fn set_start_up_current_level(&self, value: Option<u8>) -> Result<(), Error> {
         self.startup_current_level.set(value);
+        self.attributes_changed();
         Ok(())
     }
 
     async fn run<F: Fn(level_control::OutOfBandMessage)>(&self, notify: F) {
         #![allow(clippy::await_holding_refcell_ref)]
-        let mut adc = self.adc.borrow_mut();
-        let mut pin = self.pin.borrow_mut();
+        let mut knob = self.knob.borrow_mut();
+        let mut feedback = self
+            .feedback
+            .receiver()
+            .expect("More LED feedback consumers than LED_FEEDBACK_RECEIVERS");
 
-        // The min and max values measured by the variable resistor. Obtained empirically.
-        let min: u32 = 2300;
-        let max: u32 = 4081;
+        let mut filter = AnalogFilter::new(self.knob_filter.get());
+        let mut range = RangeTracker::new(self.knob_range.get());
+        let mut takeover = SoftTakeover::new(Some(Self::KNOB_TAKEOVER_THRESHOLD));
+        let mut reports = Throttle::new(Self::KNOB_REPORT_INTERVAL);
 
-        let mut ema_value: u32 = 0;
-        // Alpha = 0.2 means 20% new value, 80% old value (adjustable)
-        let alpha_num = 2; // numerator
-        let alpha_den = 10; // denominator (alpha = 0.2)
-
-        let mut old_value = 0;
+        let mut old_value = None;
 
         loop {
-            if let Ok(val) = adc.read_oneshot(&mut pin) {
-                // Exponential moving average calculation
-                ema_value =
-                    ((alpha_num * val as u32) + ((alpha_den - alpha_num) * ema_value)) / alpha_den;
-
-                // map the measured value to a level value
-                let value = ema_value
-                    .saturating_sub(min)
-                    .mul(Self::MAX_LEVEL as u32 - Self::MIN_LEVEL as u32)
-                    .div_euclid(max - min)
-                    .add(Self::MIN_LEVEL as u32)
-                    .max(Self::MIN_LEVEL as u32)
-                    .min(Self::MAX_LEVEL as u32);
-
-                if value != old_value {
-                    // Avoids small changes switching on the light.
-                    if value.abs_diff(old_value) < 5 && !self.on_off() {
-                        Timer::after_millis(50).await;
-                        continue;
-                    }
+            while let Ok(message) = self.level_requests.try_receive() {
+                notify(message);
+            }
+
+            if self.reset_knob.replace(false) {
+                if range.is_calibrating() {
+                    self.sender.send(ControlMessage::Indicate {
+                        overlay: Overlay::Maintenance,
+                        indication: None,
+                    });
+                }
+                range = RangeTracker::new(self.knob_range.get());
+            }
+
+            if self.calibrate_knob.replace(false) {
+                range.start_calibration(Instant::now());
+                self.sender.send(ControlMessage::Indicate {
+                    overlay: Overlay::Maintenance,
+                    indication: Some(Indication::Calibrating),
+                });
+            }
 
-                    old_value = value;
+            let update = match knob.read().await {
+                Ok(val) => {
+                    let now = Instant::now();
+                    let output = filter.update(val, now);
 
-                    debug!(
-                        "measured_val: {} | ema_val: {} | level: {}",
-                        val, ema_value, value
-                    );
+                    // The end stops are learned from the smoothed value, ignoring the hysteresis.
+                    let update = match filter.smoothed() {
+                        Some(smoothed) => range.observe(smoothed, now),
+                        None => range.expire(now),
+                    };
 
-                    notify(level_control::OutOfBandMessage::MoveToLevel {
-                        with_on_off: true,
-                        level: value as u8,
-                        transition_time: Some(0),
-                        options_mask: OptionsBitmap::default(),
-                        options_override: OptionsBitmap::default(),
-                    })
+                    // The level is left alone while the knob is turned from end to end.
+                    if let Some(output) = output
+                        && !range.is_calibrating()
+                    {
+                        self.knob_moved(
+                            output,
+                            range.range(),
+                            &mut takeover,
+                            &mut reports,
+                            &mut old_value,
+                            &notify,
+                        );
+                    }
+
+                    update
+                }
+                Err(_) => {
+                    error!("Error reading level");
+                    // A calibration still ends on time, so its indication does not stay up.
+                    range.expire(Instant::now())
                 }
-            } else {
-                error!("Error reading level");
+            };
+
+            if matches!(
+                update,
+                RangeUpdate::Calibrated | RangeUpdate::CalibrationFailed
+            ) {
+                self.sender.send(ControlMessage::Indicate {
+                    overlay: Overlay::Maintenance,
+                    indication: None,
+                });
+            }
+            if matches!(update, RangeUpdate::Calibrated | RangeUpdate::Drifted) {
+                self.knob_range_updates.signal(range.range());
             }
 
-            Timer::after_millis(50).await;
+            if let Some(old) = old_value {
+                self.release_knob(&mut takeover, &mut reports, old);
+            }
+            if let Some(level) = reports.poll(Instant::now()) {
+                self.report_knob_level(level, &notify);
+            }
+
+            // Levels are only reconciled while on, as the light dims to its minimum when
+            // switched off while the attribute keeps the level to restore, and once the knob
+            // reported its level, as the LED runs ahead of the attribute until then.
+            if let Some(state) = feedback
+                .try_changed()
+                .and_then(|state| self.reconcilable_state(state))
+                && self.on_off()
+                && !reports.is_pending()
+                && Some(state.level) != self.current_level()
+            {
+                debug!("LED level changed to {} by the driver", state.level);
+                notify(level_control::OutOfBandMessage::MoveToLevel {
+                    with_on_off: false,
+                    level: state.level,
+                    transition_time: Some(0),
+                    options_mask: OptionsBitmap::default(),
+                    options_override: OptionsBitmap::default(),
+                })
+            }
+
+            // Requests from the other inputs are sent straight away, even while the knob is
+            // polled slowly.
+            let now = Instant::now();
+            let mut next = now + filter.poll_period(now);
+            if let Some(deadline) = reports.deadline() {
+                next = next.min(deadline);
+            }
+            let poll = Timer::at(next);
+            if let Either::Second(message) = select(poll, self.level_requests.receive()).await {
+                notify(message);
+            }
         }
     }
 }
 
-impl<'a> ColorControlHooks for LedHandler<'a> {
-    fn set_color(&self, x: u16, y: u16) -> Result<(), Error> {
-        let x_f32 = x as f32 / 65536.0;
-        let y_f32 = y as f32 / 65536.0;
+impl<'a, B: Button, A: AnalogInput> LedHandler<'a, B, A> {
+    /// Moves to the level at the knob's `position`, once the knob controls the level.
+    fn knob_moved(
+        &self,
+        position: u16,
+        range: InputRange,
+        takeover: &mut SoftTakeover,
+        reports: &mut Throttle<u8>,
+        old_value: &mut Option<u32>,
+        notify: &impl Fn(level_control::OutOfBandMessage),
+    ) {
+        // map the measured value to a level value
+        let value = (range.position(position) as u32)
+            .mul(Self::MAX_LEVEL as u32 - Self::MIN_LEVEL as u32)
+            .div_euclid(u16::MAX as u32)
+            .add(Self::MIN_LEVEL as u32);
+
+        // The first reading only tells where the knob is, leaving the light as it started up.
+        let Some(old) = old_value.replace(value) else {
+            return;
+        };
 
-        let yxy: Yxy<D65, f32> = Yxy::new(x_f32, y_f32, 1.0);
+        self.release_knob(takeover, reports, old);
 
-        let srgb: Srgb<f32> = Srgb::from_color(yxy);
+        // Avoids small changes switching on the light.
+        if value == old || (value.abs_diff(old) < Self::KNOB_SWITCH_ON_THRESHOLD && !self.on_off())
+        {
+            *old_value = Some(old);
+            return;
+        }
 
-        let r = (srgb.red * 255.0) as u8;
-        let g = (srgb.green * 255.0) as u8;
-        let b = (srgb.blue * 255.0) as u8;
+        let current = self.current_level().unwrap_or(Self::MIN_LEVEL);
+        if !takeover.update(value as u8, current) {
+            return;
+        }
 
+        debug!("knob: {} | level: {}", position, value);
+        if self.on_off() && !self.is_faulted() {
+            self.sender.send(ControlMessage::SetBrightness {
+                level: value as u8,
+                transition: Self::LEVEL_SMOOTHING,
+            });
+            reports.push(value as u8);
+        } else {
+            // Switching on is reported straight away, as the state machine turns the LED on.
+            reports.clear();
+            self.report_knob_level(value as u8, notify);
+        }
+    }
+
+    /// Hands the level over from the knob once it was changed remotely, or by another input, so
+    /// it no longer matches the knob's position `old`. A pending report of the knob would undo
+    /// that change, so it is dropped.
+    fn release_knob(&self, takeover: &mut SoftTakeover, reports: &mut Throttle<u8>, old: u32) {
+        if self.knob_released.replace(false) {
+            takeover.release(old as u8);
+            reports.clear();
+        }
+    }
+
+    fn report_knob_level(&self, level: u8, notify: &impl Fn(level_control::OutOfBandMessage)) {
+        self.knob_level.set(Some(level));
+        notify(level_control::OutOfBandMessage::MoveToLevel {
+            with_on_off: true,
+            level,
+            transition_time: Some(0),
+            options_mask: OptionsBitmap::default(),
+            options_override: OptionsBitmap::default(),
+        })
+    }
+
+    fn send_colour(&self, colour: Rgb16, transition_time: u16) -> Result<(), Error> {
+        if self.is_faulted() {
+            error!("LED is faulted. Colour not applied");
+            return Err(ErrorCode::Failure.into());
+        }
+
+        let transition = Duration::from_millis(transition_time as u64 * 100);
+
+        self.sender.send(ControlMessage::SetColour {
+            r: colour.r,
+            g: colour.g,
+            b: colour.b,
+            transition,
+        });
+        Ok(())
+    }
+}
+
+impl<'a, B: Button, A: AnalogInput> ColorControlHooks for LedHandler<'a, B, A> {
+    fn set_color(&self, x: u16, y: u16, transition_time: u16) -> Result<(), Error> {
+        self.send_colour(xy_to_rgb(x, y), transition_time)
+    }
+
+    fn set_color_temperature(&self, mireds: u16, transition_time: u16) -> Result<(), Error> {
+        // On RGBW LEDs, the output extracts the white content of this colour into the white LED.
+        self.send_colour(mireds_to_rgb(mireds), transition_time)
+    }
+
+    fn remaining_time(&self) -> u16 {
+        self.led_state()
+            .map(|state| state.progress.colour.as_millis().div_ceil(100) as u16)
+            .unwrap_or(0)
+    }
+
+    async fn run<F: Fn(color_control::OutOfBandMessage)>(&self, notify: F) {
+        loop {
+            notify(self.colour_requests.receive().await);
+        }
+    }
+}
+
+impl<'a, B: Button, A: AnalogInput> EncoderTarget for LedHandler<'a, B, A> {
+    fn step(&self, mode: EncoderMode, detents: i32) {
+        match mode {
+            EncoderMode::Level => {
+                let up = detents > 0;
+                let size = (detents.unsigned_abs() * Self::ENCODER_LEVEL_STEP).min(u8::MAX as u32);
+                // As when dimming with the button, only switch on when brightening.
+                self.request_level(level_control::OutOfBandMessage::Step {
+                    with_on_off: up,
+                    step_mode: if up {
+                        level_control::StepModeEnum::Up
+                    } else {
+                        level_control::StepModeEnum::Down
+                    },
+                    step_size: size as u8,
+                    transition_time: Some(0),
+                    options_mask: OptionsBitmap::default(),
+                    options_override: OptionsBitmap::default(),
+                });
+            }
+            EncoderMode::Hue => {
+                let degrees = (detents * Self::ENCODER_HUE_STEP).clamp(-180, 180);
+                self.request_colour(color_control::OutOfBandMessage::StepHue {
+                    degrees: degrees as i16,
+                });
+            }
+            EncoderMode::ColourTemperature => {
+                // Clockwise turns cooler, towards fewer mireds.
+                let mireds = (-detents * Self::ENCODER_MIREDS_STEP).clamp(-500, 500);
+                self.request_colour(color_control::OutOfBandMessage::StepColorTemperature {
+                    mireds: mireds as i16,
+                });
+            }
+        }
+    }
+}
+
+impl<'a, B: Button, A: AnalogInput> Resettable for LedHandler<'a, B, A> {
+    /// Returns the OnOff and LevelControl attributes and the potentiometer's end stops to their
+    /// defaults, which match the reset LED driver. The defaults are signalled to supersede any
+    /// attributes still waiting to be saved; as they match a removed setting, none are written.
+    ///
+    /// The LevelControl `OnLevel`, `Options`, `OnOffTransitionTime`, `OnTransitionTime` and
+    /// `OffTransitionTime` are deliberately kept: they live in the LevelControl handler, which
+    /// offers no way to change them other than a controller's write, and are not saved, so they
+    /// return to their defaults at the next restart.
+    fn reset(&self) {
+        let attributes = LightAttributes::DEFAULT;
+        self.on_off.set(attributes.on_off);
+        self.start_up_on_off.set(attributes.start_up_on_off);
+        self.current_level.set(attributes.current_level);
+        self.startup_current_level
+            .set(attributes.start_up_current_level);
+        self.attributes_changed();
+
+        // The knob is picked up again once turned past the level the driver resets to.
+        self.knob_range.set(Self::DEFAULT_KNOB_RANGE);
+        self.calibrate_knob.set(false);
+        self.reset_knob.set(true);
+        self.knob_released.set(true);
+        debug!("LedHandler attributes reset");
+    }
+}
+
+impl<'a, B: Button, A: AnalogInput> LampNotificationHooks for LedHandler<'a, B, A> {
+    fn flash(&self, x: u16, y: u16, length: FlashLength, period: u16) -> Result<(), Error> {
+        if self.is_faulted() {
+            error!("LED is faulted. Notification not shown");
+            return Err(ErrorCode::Failure.into());
+        }
+
+        let tenths = |value: u16| Duration::from_millis(value as u64 * 100);
+        let repeat = match length {
+            FlashLength::Count(count) => Repeat::Count(count),
+            FlashLength::Duration(duration) => Repeat::For(tenths(duration)),
+        };
+
+        self.sender.notify(Indication::Flash {
+            colour: xy_to_rgb(x, y),
+            period: tenths(period),
+            repeat,
+        });
+        Ok(())
+    }
+}
+
+impl<'a, B: Button, A: AnalogInput> IdentifyHooks for LedHandler<'a, B, A> {
+    fn identify_type(&self) -> IdentifyTypeEnum {
+        IdentifyTypeEnum::LightOutput
+    }
+
+    /// Shows identification on [`Overlay::Identify`], above the light and notifications.
+    fn identify(&self, action: IdentifyAction) {
+        const WHITE: Rgb16 = Rgb16::new(u16::MAX, u16::MAX, u16::MAX);
+        const GREEN: Rgb16 = Rgb16::new(0, u16::MAX, 0);
+        const ORANGE: Rgb16 = Rgb16::new(u16::MAX, 0x8000, 0);
+        let second = Duration::from_secs(1);
+
+        let indication = match action {
+            // The cluster cancels identification once the time is up.
+            IdentifyAction::Time(_) => Some(Indication::Flash {
+                colour: WHITE,
+                period: second,
+                repeat: Repeat::For(Duration::MAX),
+            }),
+            IdentifyAction::Cancel
+            | IdentifyAction::Effect(EffectIdentifierEnum::StopEffect, _) => None,
+            IdentifyAction::Effect(EffectIdentifierEnum::FinishEffect, _) => {
+                self.sender
+                    .send(ControlMessage::FinishIndication(Overlay::Identify));
+                return;
+            }
+            IdentifyAction::Effect(EffectIdentifierEnum::Blink, _) => Some(Indication::Flash {
+                colour: WHITE,
+                period: second,
+                repeat: Repeat::Count(1),
+            }),
+            IdentifyAction::Effect(EffectIdentifierEnum::Breathe, _) => Some(Indication::Breathe {
+                colour: WHITE,
+                period: second,
+                repeat: Repeat::Count(15),
+            }),
+            IdentifyAction::Effect(EffectIdentifierEnum::Okay, _) => Some(Indication::Flash {
+                colour: GREEN,
+                period: second * 2,
+                repeat: Repeat::For(second),
+            }),
+            IdentifyAction::Effect(EffectIdentifierEnum::ChannelChange, _) => {
+                Some(Indication::Flash {
+                    colour: ORANGE,
+                    period: second * 16,
+                    repeat: Repeat::For(second * 8),
+                })
+            }
+        };
+
+        self.sender.send(ControlMessage::Indicate {
+            overlay: Overlay::Identify,
+            indication,
+        });
+    }
+}
+
+impl<'a, B: Button, A: AnalogInput> LedHandler<'a, B, A> {
+    /// Sends `calibration` to the driver, which reports it back to be saved.
+    fn send_calibration(&self, calibration: Calibration) -> Result<(), Error> {
+        if !calibration.is_valid() {
+            error!("Invalid LED calibration. Not applied");
+            return Err(ErrorCode::ConstraintError.into());
+        }
+
+        self.calibration.set(Some(calibration));
         self.sender
-            .try_send(ControlMessage::SetColour { r, g, b })
-            .map_err(|_| ErrorCode::Busy.into())
+            .send(ControlMessage::SetCalibration(calibration));
+        Ok(())
+    }
+}
+
+impl<'a, B: Button, A: AnalogInput> LampMaintenanceHooks for LedHandler<'a, B, A> {
+    fn power_limited(&self) -> bool {
+        self.feedback
+            .try_get()
+            .is_some_and(|state| state.power_limited)
+    }
+
+    fn frame_rate(&self) -> u8 {
+        self.frame_rate.get().unwrap_or_else(|| {
+            let frame_rate = self
+                .feedback
+                .try_get()
+                .map_or(DEFAULT_FRAME_RATE, |state| state.frame_rate);
+            frame_rate.min(u8::MAX as u32) as u8
+        })
+    }
+
+    fn set_frame_rate(&self, frame_rate: u8) {
+        self.frame_rate.set(Some(frame_rate));
+        self.sender
+            .send(ControlMessage::SetFrameRate(frame_rate as u32));
+    }
+
+    fn frame_stats(&self) -> FrameStats {
+        self.frame_monitor
+            .map(FrameMonitor::stats)
+            .unwrap_or_default()
+    }
+
+    fn reset_frame_stats(&self) {
+        if let Some(monitor) = self.frame_monitor {
+            monitor.reset();
+        }
+    }
+
+    fn status_suppressed(&self) -> bool {
+        self.status_indicator
+            .is_some_and(StatusIndicator::is_suppressed)
+    }
+
+    fn set_status_suppressed(&self, suppressed: bool) {
+        if let Some(indicator) = self.status_indicator {
+            indicator.set_suppressed(suppressed);
+        }
+    }
+
+    fn calibrate_knob(&self) -> Result<(), Error> {
+        self.start_knob_calibration();
+        Ok(())
+    }
+
+    fn set_channel_calibration(
+        &self,
+        channel: usize,
+        gamma: f32,
+        mix: [f32; 3],
+        gain: f32,
+    ) -> Result<(), Error> {
+        let mut calibration = self
+            .calibration
+            .get()
+            .or_else(|| self.led_state().map(|state| state.calibration))
+            .unwrap_or_default();
+
+        calibration.gamma[channel] = gamma;
+        calibration.matrix[channel] = mix;
+        calibration.gain[channel] = gain;
+        self.send_calibration(calibration)
+    }
+
+    fn reset_calibration(&self) -> Result<(), Error> {
+        self.send_calibration(Calibration::default())
+    }
+
+    fn set_brightness_curve(&self, curve: BrightnessCurve) -> Result<(), Error> {
+        self.sender.send(ControlMessage::SetBrightnessCurve(curve));
+        self.curve_updates.signal(curve);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use rs_matter_embassy::matter::dm::clusters::identify::EffectVariantEnum;
+
+    use super::*;
+    use crate::led::led_channel::LedChannel;
+    use crate::led::led_driver::{Fault, Mode, Progress};
+    use crate::test_util::drain;
+
+    /// A button which is never pressed.
+    struct MockButton;
+
+    impl Button for MockButton {
+        type Error = ();
+
+        fn is_pressed(&mut self) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        async fn wait_for_change(&mut self) -> Result<(), Self::Error> {
+            core::future::pending().await
+        }
+    }
+
+    /// A knob left at one reading.
+    struct MockAnalogInput(u16);
+
+    impl AnalogInput for MockAnalogInput {
+        type Error = ();
+
+        async fn read(&mut self) -> Result<u16, Self::Error> {
+            Ok(self.0)
+        }
+    }
+
+    type TestHandler<'a> = LedHandler<'a, MockButton, MockAnalogInput>;
+
+    fn handler<'a>(channel: &'a LedChannel, feedback: &'a LedFeedback) -> TestHandler<'a> {
+        LedHandler::new(
+            channel.sender(),
+            feedback,
+            MockButton,
+            MockAnalogInput(2300),
+        )
+    }
+
+    /// Fixed LevelControl transition times, in tenths of a second.
+    struct Transitions {
+        on: Option<u16>,
+        off: Option<u16>,
+        on_off: u16,
+    }
+
+    impl OnOffTransitions for Transitions {
+        fn on_transition_time(&self) -> Option<u16> {
+            self.on
+        }
+
+        fn off_transition_time(&self) -> Option<u16> {
+            self.off
+        }
+
+        fn on_off_transition_time(&self) -> u16 {
+            self.on_off
+        }
+    }
+
+    #[test]
+    fn set_on_off_switches_over_the_default_transition() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let handler = handler(&channel, &feedback);
+
+        handler.set_on_off(false);
+        assert_eq!(
+            drain(&channel),
+            [ControlMessage::SetOn {
+                on: false,
+                transition: Duration::from_millis(500),
+            }]
+        );
+        assert!(!handler.on_off());
+
+        let saved = handler.attribute_updates().try_take().unwrap();
+        assert!(!saved.on_off);
+        assert_eq!(saved.current_level, LightAttributes::DEFAULT.current_level);
+    }
+
+    #[test]
+    fn set_on_off_follows_the_level_control_transition_times() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let handler = handler(&channel, &feedback);
+        let transitions = Transitions {
+            on: Some(20),
+            off: None,
+            on_off: 3,
+        };
+        handler.set_on_off_transitions(&transitions);
+
+        handler.set_on_off(true);
+        assert_eq!(
+            drain(&channel),
+            [ControlMessage::SetOn {
+                on: true,
+                transition: Duration::from_secs(2),
+            }]
+        );
+
+        handler.set_on_off(false);
+        assert_eq!(
+            drain(&channel),
+            [ControlMessage::SetOn {
+                on: false,
+                transition: Duration::from_millis(300),
+            }]
+        );
+    }
+
+    #[test]
+    fn set_device_level_smooths_the_brightness() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let handler = handler(&channel, &feedback);
+
+        assert_eq!(handler.set_device_level(42), Ok(Some(42)));
+        assert_eq!(
+            drain(&channel),
+            [ControlMessage::SetBrightness {
+                level: 42,
+                transition: TestHandler::LEVEL_SMOOTHING,
+            }]
+        );
+
+        // The cluster stores the level reached, which is then saved.
+        handler.set_current_level(Some(42));
+        assert_eq!(handler.current_level(), Some(42));
+        assert_eq!(
+            handler
+                .attribute_updates()
+                .try_take()
+                .unwrap()
+                .current_level,
+            Some(42)
+        );
+    }
+
+    #[test]
+    fn set_device_level_fails_while_the_led_is_faulted() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let handler = handler(&channel, &feedback);
+
+        feedback.sender().send(LedState {
+            on: true,
+            level: 100,
+            colour: Rgb16::new(0, 0, 0),
+            mode: Mode::Solid,
+            progress: Progress::default(),
+            calibration: Calibration::default(),
+            power_limited: false,
+            frame_rate: 50,
+            fault: Some(Fault::Write),
+        });
+
+        assert_eq!(handler.set_device_level(42), Err(()));
+        assert!(drain(&channel).is_empty());
+    }
+
+    #[test]
+    fn start_up_attributes_are_saved() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let handler = handler(&channel, &feedback);
+
+        handler
+            .set_start_up_on_off(Nullable::some(StartUpOnOffEnum::Toggle))
+            .unwrap();
+        handler.set_start_up_current_level(Some(10)).unwrap();
+
+        assert_eq!(
+            handler.start_up_on_off().into_option(),
+            Some(StartUpOnOffEnum::Toggle)
+        );
+        assert_eq!(handler.start_up_current_level().unwrap(), Some(10));
+
+        let saved = handler.attribute_updates().try_take().unwrap();
+        assert_eq!(saved.start_up_on_off, Some(StartUpOnOffEnum::Toggle));
+        assert_eq!(saved.start_up_current_level, Some(10));
+        assert!(drain(&channel).is_empty());
+    }
+
+    #[test]
+    fn saved_attributes_are_shown_at_once() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let handler = handler(&channel, &feedback).with_attributes(LightAttributes {
+            on_off: false,
+            current_level: Some(80),
+            ..LightAttributes::DEFAULT
+        });
+
+        assert!(!handler.on_off());
+        assert_eq!(handler.current_level(), Some(80));
+        assert_eq!(
+            drain(&channel),
+            [
+                ControlMessage::SetOn {
+                    on: false,
+                    transition: Duration::from_ticks(0),
+                },
+                ControlMessage::SetBrightness {
+                    level: 80,
+                    transition: Duration::from_ticks(0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn off_with_effect_switches_off_through_the_driver() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let handler = handler(&channel, &feedback);
+
+        block_on(
+            handler.handle_off_with_effect(on_off::EffectVariantEnum::DelayedAllOff(
+                on_off::DelayedAllOffEffectVariantEnum::NoFade,
+            )),
+        );
+
+        assert!(!handler.on_off());
+        assert!(!handler.attribute_updates().try_take().unwrap().on_off);
+        assert_eq!(
+            drain(&channel),
+            [ControlMessage::OffWithEffect(OffEffect::NoFade)]
+        );
+    }
+
+    #[test]
+    fn identify_shows_on_the_identify_overlay() {
+        let channel = LedChannel::new();
+        let feedback = LedFeedback::new();
+        let handler = handler(&channel, &feedback);
+
+        handler.identify(IdentifyAction::Time(10));
+        assert!(matches!(
+            drain(&channel)[..],
+            [ControlMessage::Indicate {
+                overlay: Overlay::Identify,
+                indication: Some(Indication::Flash { .. }),
+            }]
+        ));
+
+        handler.identify(IdentifyAction::Effect(
+            EffectIdentifierEnum::Breathe,
+            EffectVariantEnum::Default,
+        ));
+        assert!(matches!(
+            drain(&channel)[..],
+            [ControlMessage::Indicate {
+                overlay: Overlay::Identify,
+                indication: Some(Indication::Breathe { .. }),
+            }]
+        ));
+
+        handler.identify(IdentifyAction::Effect(
+            EffectIdentifierEnum::FinishEffect,
+            EffectVariantEnum::Default,
+        ));
+        assert_eq!(
+            drain(&channel),
+            [ControlMessage::FinishIndication(Overlay::Identify)]
+        );
+
+        handler.identify(IdentifyAction::Cancel);
+        assert_eq!(
+            drain(&channel),
+            [ControlMessage::Indicate {
+                overlay: Overlay::Identify,
+                indication: None,
+            }]
+        );
     }
 }
@@ -0,0 +1,185 @@
+use embassy_time::Duration;
+
+use crate::led::led_colour::Rgb16;
+
+const RED: Rgb16 = Rgb16::new(u16::MAX, 0, 0);
+const GREEN: Rgb16 = Rgb16::new(0, u16::MAX, 0);
+const BLUE: Rgb16 = Rgb16::new(0, 0, u16::MAX);
+const AMBER: Rgb16 = Rgb16::new(u16::MAX, 0x7fff, 0);
+const WHITE: Rgb16 = Rgb16::new(u16::MAX, u16::MAX, u16::MAX);
+const CYAN: Rgb16 = Rgb16::new(0, u16::MAX, u16::MAX);
+
+/// Number of flashes confirming a factory reset.
+const RESET_FLASHES: u64 = 3;
+
+/// Time the light stays on, and then off, for each flash confirming a factory reset.
+const RESET_FLASH_TIME: Duration = Duration::from_millis(250);
+
+/// Time to breathe in and out once while the commissioning window is open.
+const BREATHING_PERIOD: Duration = Duration::from_secs(3);
+
+/// Time to blink on and off once while the network is lost.
+const NETWORK_LOST_PERIOD: Duration = Duration::from_secs(1);
+
+/// Number of flashes confirming commissioning.
+const COMMISSIONED_FLASHES: u64 = 2;
+
+/// Time the light stays on, and then off, for each flash confirming commissioning.
+const COMMISSIONED_FLASH_TIME: Duration = Duration::from_millis(300);
+
+/// Time to blink on and off once while calibrating an input.
+const CALIBRATING_PERIOD: Duration = Duration::from_millis(400);
+
+/// Temporary patterns shown in place of the light, e.g. to give feedback on a button press.
+///
+/// Indications are shown whether the light is on or off, and leave its state untouched.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Indication {
+    /// Counts down the time a button has to be held for, blinking red faster as it runs out.
+    /// The light stays red once the time is up, until the indication is replaced.
+    Countdown { duration: Duration },
+    /// Flashes white to confirm that a factory reset completed.
+    ResetConfirmed,
+    /// Breathes blue while the commissioning window is open.
+    Commissioning,
+    /// Blinks amber while the network is lost.
+    NetworkLost,
+    /// Flashes green to confirm that the device was commissioned.
+    Commissioned,
+    /// Blinks cyan while learning the end stops of an input.
+    Calibrating,
+    /// Flashes `colour` on and off once every `period`, e.g. as a doorbell.
+    Flash {
+        colour: Rgb16,
+        period: Duration,
+        repeat: Repeat,
+    },
+    /// Fades `colour` in and out once every `period`.
+    Breathe {
+        colour: Rgb16,
+        period: Duration,
+        repeat: Repeat,
+    },
+}
+
+/// How long a [`Indication::Flash`] goes on for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Repeat {
+    /// A number of flashes.
+    Count(u16),
+    /// Flashes until the time is up.
+    For(Duration),
+}
+
+impl Indication {
+    /// The colour and output shown `elapsed` after the indication started, with `u16::MAX`
+    /// being full output. Returns `None` once the indication has finished.
+    pub fn frame(&self, elapsed: Duration) -> Option<(Rgb16, u16)> {
+        let lit = |on: bool| if on { u16::MAX } else { 0 };
+
+        match *self {
+            Self::Countdown { duration } => {
+                let third = (duration / 3).as_millis().max(1);
+                let elapsed = elapsed.as_millis();
+
+                // Blink twice a second, then four times, then eight times.
+                let (period, offset) = match elapsed / third {
+                    0 => (500, elapsed),
+                    1 => (250, elapsed - third),
+                    2 => (125, elapsed - 2 * third),
+                    _ => return Some((RED, u16::MAX)),
+                };
+
+                Some((RED, lit(offset % period < period / 2)))
+            }
+            Self::ResetConfirmed => flashes(WHITE, RESET_FLASHES, RESET_FLASH_TIME, elapsed),
+            Self::Commissioning => Some((BLUE, breathe(BREATHING_PERIOD, elapsed))),
+            Self::NetworkLost => {
+                let period = NETWORK_LOST_PERIOD.as_millis();
+                Some((AMBER, lit(elapsed.as_millis() % period < period / 2)))
+            }
+            Self::Commissioned => flashes(
+                GREEN,
+                COMMISSIONED_FLASHES,
+                COMMISSIONED_FLASH_TIME,
+                elapsed,
+            ),
+            Self::Calibrating => {
+                let period = CALIBRATING_PERIOD.as_millis();
+                Some((CYAN, lit(elapsed.as_millis() % period < period / 2)))
+            }
+            Self::Flash {
+                colour,
+                period,
+                repeat,
+            } => match repeat {
+                Repeat::Count(count) => flashes(colour, count as u64, period / 2, elapsed),
+                Repeat::For(duration) if elapsed >= duration => None,
+                Repeat::For(_) => flashes(colour, u64::MAX / 2, period / 2, elapsed),
+            },
+            Self::Breathe {
+                colour,
+                period,
+                repeat,
+            } => {
+                let end = match repeat {
+                    Repeat::Count(count) => period * count as u32,
+                    Repeat::For(duration) => duration,
+                };
+                (elapsed < end).then(|| (colour, breathe(period, elapsed)))
+            }
+        }
+    }
+
+    /// The indication ending with the cycle running `elapsed` after it started, as asked for by
+    /// a controller finishing an effect. Indications without repeating cycles end at once, and
+    /// `None` is returned for them.
+    pub fn finishing(&self, elapsed: Duration) -> Option<Self> {
+        let (Self::Flash { period, repeat, .. } | Self::Breathe { period, repeat, .. }) = self
+        else {
+            return None;
+        };
+
+        let cycles = (elapsed.as_ticks() / period.as_ticks().max(1) + 1).min(u16::MAX as u64);
+        let end = match *repeat {
+            Repeat::Count(count) => *period * count as u32,
+            Repeat::For(duration) => duration,
+        };
+        if end <= *period * cycles as u32 {
+            // Already ends with this cycle.
+            return Some(*self);
+        }
+
+        let mut finishing = *self;
+        if let Self::Flash { repeat, .. } | Self::Breathe { repeat, .. } = &mut finishing {
+            *repeat = Repeat::Count(cycles as u16);
+        }
+        Some(finishing)
+    }
+}
+
+/// The output of a breath lasting `period`, `elapsed` after the first one started. Rises for
+/// the first half of the period and falls for the second.
+fn breathe(period: Duration, elapsed: Duration) -> u16 {
+    let period = period.as_millis().max(1);
+    let offset = elapsed.as_millis() % period;
+    let rise = offset.min(period - offset) * 2;
+
+    (rise * u16::MAX as u64 / period) as u16
+}
+
+/// Flashes `colour` `count` times, staying on and then off for `time` each.
+fn flashes(colour: Rgb16, count: u64, time: Duration, elapsed: Duration) -> Option<(Rgb16, u16)> {
+    let half = time.as_millis().max(1);
+    let elapsed = elapsed.as_millis();
+    if elapsed >= count.saturating_mul(2 * half) {
+        return None;
+    }
+
+    let level = if (elapsed / half).is_multiple_of(2) {
+        u16::MAX
+    } else {
+        0
+    };
+    Some((colour, level))
+}
@@ -0,0 +1,191 @@
+#[cfg(feature = "esp")]
+use esp_hal::{
+    gpio::AnyPin,
+    peripherals,
+    rmt::{PulseCode, Rmt},
+    time::Rate,
+};
+#[cfg(feature = "esp")]
+use esp_hal_smartled::{LedAdapterError, SmartLedsAdapterAsync, buffer_size_async};
+#[cfg(feature = "esp")]
+use smart_leds::SmartLedsWriteAsync;
+use smart_leds::{RGB8, gamma};
+
+use crate::led::led_colour::{Rgb16, mireds_to_rgb};
+
+/// A backend that physically drives the LED.
+#[allow(async_fn_in_trait)]
+pub trait LedOutput {
+    type Error;
+
+    /// Bits per channel the output resolves. The driver dithers drive values down to them.
+    fn bits(&self) -> u32 {
+        8
+    }
+
+    /// Writes `colour`, given as linear drive values with gamma and level already applied, and
+    /// reduced to [`LedOutput::bits`] per channel.
+    async fn write(&mut self, colour: Rgb16) -> Result<(), Self::Error>;
+}
+
+// Configure RMT (Remote Control Transceiver) peripheral globally
+// <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/peripherals/rmt.html>
+#[cfg(feature = "esp")]
+fn init_rmt(rmt: peripherals::RMT<'_>) -> Rmt<'_, esp_hal::Async> {
+    let frequency: Rate = { Rate::from_mhz(80) };
+    Rmt::new(rmt, frequency)
+        .expect("Failed to initialize RMT")
+        .into_async()
+}
+
+/// Drives a single WS2812 RGB pixel through the RMT peripheral.
+#[cfg(feature = "esp")]
+pub struct SmartLedOutput<'a> {
+    led: SmartLedsAdapterAsync<'a, { buffer_size_async(1) }>,
+}
+
+#[cfg(feature = "esp")]
+impl<'a> SmartLedOutput<'a> {
+    pub fn new(rmt: peripherals::RMT<'a>, pin: AnyPin<'a>) -> Self {
+        let rmt = init_rmt(rmt);
+
+        // We use one of the RMT channels to instantiate a `SmartLedsAdapterAsync` which can
+        // be used directly with all `smart_led` implementations
+        let rmt_channel = rmt.channel0;
+        let rmt_buffer = [PulseCode::default(); buffer_size_async(1)];
+
+        Self {
+            led: SmartLedsAdapterAsync::new(rmt_channel, pin, rmt_buffer),
+        }
+    }
+}
+
+#[cfg(feature = "esp")]
+impl LedOutput for SmartLedOutput<'_> {
+    type Error = LedAdapterError;
+
+    async fn write(&mut self, colour: Rgb16) -> Result<(), Self::Error> {
+        self.led.write([RGB8::from(colour)]).await
+    }
+}
+
+/// The colour of a white LED, expressed as the linear RGB drive values that match its output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WhiteChannel {
+    colour: RGB8,
+}
+
+impl WhiteChannel {
+    /// A white LED with the given correlated colour temperature.
+    pub fn from_kelvin(kelvin: u16) -> Self {
+        let srgb = RGB8::from(mireds_to_rgb((1_000_000 / kelvin.max(1) as u32) as u16));
+        let colour = gamma([srgb].into_iter()).next().unwrap_or(srgb);
+
+        Self { colour }
+    }
+
+    pub fn colour(&self) -> RGB8 {
+        self.colour
+    }
+
+    /// Splits `colour` into the level of the white LED and the remaining RGB correction.
+    ///
+    /// The white LED takes as much of `colour` as it can reproduce on its own. Colours close to
+    /// the white LED's own temperature are therefore rendered almost entirely by it.
+    pub fn extract(&self, colour: Rgb16) -> (Rgb16, u16) {
+        let own = Rgb16::from(self.colour);
+        let share = |value: u16, white: u16| match white {
+            0 => u16::MAX as u32,
+            white => value as u32 * u16::MAX as u32 / white as u32,
+        };
+
+        let white = share(colour.r, own.r)
+            .min(share(colour.g, own.g))
+            .min(share(colour.b, own.b))
+            .min(u16::MAX as u32);
+
+        let residual = |value: u16, channel: u16| {
+            let covered = (white * channel as u32 + 0x7fff) / u16::MAX as u32;
+            value.saturating_sub(covered as u16)
+        };
+
+        let rgb = Rgb16::new(
+            residual(colour.r, own.r),
+            residual(colour.g, own.g),
+            residual(colour.b, own.b),
+        );
+
+        (rgb, white as u16)
+    }
+}
+
+/// Finds the levels of the cold and warm white LEDs whose combined output best matches `colour`.
+pub fn mix_whites(colour: Rgb16, cold: WhiteChannel, warm: WhiteChannel) -> (u16, u16) {
+    let vector = |colour: RGB8| [colour.r as f32, colour.g as f32, colour.b as f32];
+    let dot = |a: [f32; 3], b: [f32; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+
+    // The whites are given in 8-bit drive values.
+    let target = [colour.r, colour.g, colour.b].map(|value| value as f32 / 257.0);
+    let cold = vector(cold.colour());
+    let warm = vector(warm.colour());
+
+    // Least squares fit of `target = a * cold + b * warm`.
+    let (cc, ww, cw) = (dot(cold, cold), dot(warm, warm), dot(cold, warm));
+    let (ct, wt) = (dot(cold, target), dot(warm, target));
+    let det = cc * ww - cw * cw;
+
+    // The determinant is never negative, and only zero when both LEDs have the same colour.
+    let (a, b) = if det <= f32::EPSILON * cc * ww {
+        // Share the load evenly between the two LEDs.
+        let a = ct / (cc + ww).max(f32::EPSILON);
+        (a, a)
+    } else {
+        ((ww * ct - cw * wt) / det, (cc * wt - cw * ct) / det)
+    };
+
+    // Colours outside of what the two whites can mix clamp to the nearest of them.
+    let (a, b) = (a.max(0.0), b.max(0.0));
+    let excess = a.max(b).max(1.0);
+    let level = |value: f32| (value / excess * u16::MAX as f32) as u16;
+
+    (level(a), level(b))
+}
+
+/// Drives a single SK6812 RGBW pixel through the RMT peripheral.
+///
+/// The SK6812 uses the WS2812 protocol with a fourth byte per pixel for its white LED. Its GRBW
+/// bytes are therefore packed into the GRB stream of two RGB pixels, the surplus bytes being
+/// ignored as there is no further pixel to pass them on to.
+#[cfg(feature = "esp")]
+pub struct SmartLedRgbwOutput<'a> {
+    led: SmartLedsAdapterAsync<'a, { buffer_size_async(2) }>,
+    white: WhiteChannel,
+}
+
+#[cfg(feature = "esp")]
+impl<'a> SmartLedRgbwOutput<'a> {
+    pub fn new(rmt: peripherals::RMT<'a>, pin: AnyPin<'a>, white: WhiteChannel) -> Self {
+        let rmt = init_rmt(rmt);
+
+        let rmt_channel = rmt.channel0;
+        let rmt_buffer = [PulseCode::default(); buffer_size_async(2)];
+
+        Self {
+            led: SmartLedsAdapterAsync::new(rmt_channel, pin, rmt_buffer),
+            white,
+        }
+    }
+}
+
+#[cfg(feature = "esp")]
+impl LedOutput for SmartLedRgbwOutput<'_> {
+    type Error = LedAdapterError;
+
+    async fn write(&mut self, colour: Rgb16) -> Result<(), Self::Error> {
+        let (rgb, white) = self.white.extract(colour);
+
+        // The adapter sends each pixel in G, R, B order.
+        let pixels = [RGB8::from(rgb), RGB8::from(Rgb16::new(0, white, 0))];
+        self.led.write(pixels).await
+    }
+}
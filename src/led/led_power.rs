@@ -0,0 +1,207 @@
+use crate::led::led_colour::Rgb16;
+use crate::led::led_output::{WhiteChannel, mix_whites};
+
+/// The LEDs making up one pixel, and the current each draws at full drive, in mA.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelLeds {
+    /// Red, green and blue LEDs.
+    Rgb { rgb_ma: [u16; 3] },
+    /// Red, green and blue LEDs with a white LED, which takes over part of each colour.
+    Rgbw {
+        rgb_ma: [u16; 3],
+        white: WhiteChannel,
+        white_ma: u16,
+    },
+    /// Cold and warm white LEDs, mixed to the nearest colour they can show together.
+    Cct {
+        cold: WhiteChannel,
+        warm: WhiteChannel,
+        white_ma: [u16; 2],
+    },
+}
+
+/// Current drawn by the LEDs and the maximum the supply can deliver, used to keep bright frames
+/// within the rating of the USB port or power supply.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PowerBudget {
+    pub leds: PixelLeds,
+    /// Current drawn by one pixel while dark, in mA.
+    pub idle_ma: u16,
+    /// Number of pixels showing the colour.
+    pub led_count: u16,
+    /// Maximum current of all pixels together, in mA.
+    pub limit_ma: u32,
+}
+
+impl PowerBudget {
+    /// Estimated current drawn by every pixel showing `colour`, given as the linear drive values
+    /// written to the output, in µA.
+    pub fn estimate_ua(&self, colour: Rgb16) -> u32 {
+        let channel =
+            |value: u16, ma: u16| (value as u64 * ma as u64 * 1000 / u16::MAX as u64) as u32;
+        let rgb = |colour: Rgb16, ma: [u16; 3]| {
+            channel(colour.r, ma[0]) + channel(colour.g, ma[1]) + channel(colour.b, ma[2])
+        };
+
+        let drive = match self.leds {
+            PixelLeds::Rgb { rgb_ma } => rgb(colour, rgb_ma),
+            PixelLeds::Rgbw {
+                rgb_ma,
+                white,
+                white_ma,
+            } => {
+                let (colour, level) = white.extract(colour);
+                rgb(colour, rgb_ma) + channel(level, white_ma)
+            }
+            PixelLeds::Cct {
+                cold,
+                warm,
+                white_ma,
+            } => {
+                let (cold, warm) = mix_whites(colour, cold, warm);
+                channel(cold, white_ma[0]) + channel(warm, white_ma[1])
+            }
+        };
+
+        (self.idle_ma as u32 * 1000 + drive) * self.led_count as u32
+    }
+
+    /// Scales `colour` down so that the pixels stay within the budget. Returns the scaled colour
+    /// and whether it had to be limited.
+    pub fn limit(&self, colour: Rgb16) -> (Rgb16, bool) {
+        let limit = self.limit_ma * 1000;
+        if self.estimate_ua(colour) <= limit {
+            return (colour, false);
+        }
+
+        // The current grows with the scale, but not always in proportion to it, e.g. not while
+        // the whites of a CCT pixel are saturated. Search for the largest scale within the
+        // budget, which is none at all if the idle current alone exceeds it.
+        let scaled = |scale: u32| {
+            let channel = |value: u16| ((value as u32 * scale) >> 16) as u16;
+            Rgb16::new(channel(colour.r), channel(colour.g), channel(colour.b))
+        };
+        let (mut within, mut over) = (0, 1 << 16);
+        while over - within > 1 {
+            let scale = (within + over) / 2;
+            if self.estimate_ua(scaled(scale)) <= limit {
+                within = scale;
+            } else {
+                over = scale;
+            }
+        }
+
+        (scaled(within), true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Rgb16 = Rgb16::new(u16::MAX, u16::MAX, u16::MAX);
+
+    fn budget(leds: PixelLeds, limit_ma: u32) -> PowerBudget {
+        PowerBudget {
+            leds,
+            idle_ma: 1,
+            led_count: 2,
+            limit_ma,
+        }
+    }
+
+    const RGB: PixelLeds = PixelLeds::Rgb {
+        rgb_ma: [10, 20, 30],
+    };
+
+    #[test]
+    fn estimates_from_16_bit_drive_values() {
+        let budget = budget(RGB, 1000);
+        assert_eq!(budget.estimate_ua(Rgb16::default()), 2 * 1000);
+        assert_eq!(budget.estimate_ua(WHITE), 2 * (1 + 10 + 20 + 30) * 1000);
+
+        // Drive values below one 8-bit step still draw current.
+        assert_eq!(budget.estimate_ua(Rgb16::new(0, 0, 128)), 2 * (1000 + 58));
+    }
+
+    #[test]
+    fn colours_within_the_budget_are_kept() {
+        let budget = budget(RGB, 122);
+        assert_eq!(budget.limit(WHITE), (WHITE, false));
+    }
+
+    #[test]
+    fn colours_over_the_budget_are_scaled_down() {
+        let budget = budget(RGB, 62);
+        let (limited, is_limited) = budget.limit(WHITE);
+        assert!(is_limited);
+        assert!(budget.estimate_ua(limited) <= 62 * 1000);
+        // Half the drive current stays within the budget, the idle current is always drawn.
+        assert!(limited.r.abs_diff(u16::MAX / 2) <= 0x100);
+        assert_eq!((limited.r, limited.g), (limited.g, limited.b));
+    }
+
+    #[test]
+    fn idle_current_over_the_budget_turns_the_pixels_off() {
+        let budget = budget(RGB, 1);
+        assert_eq!(budget.limit(WHITE), (Rgb16::default(), true));
+    }
+
+    #[test]
+    fn white_led_takes_over_the_colour_leds() {
+        let white = WhiteChannel::from_kelvin(4000);
+        let budget = budget(
+            PixelLeds::Rgbw {
+                rgb_ma: [20; 3],
+                white,
+                white_ma: 20,
+            },
+            1000,
+        );
+
+        // The white LED alone shows its own colour.
+        let own = Rgb16::from(white.colour());
+        assert!(budget.estimate_ua(own).abs_diff(2 * (1 + 20) * 1000) <= 2 * 1000);
+    }
+
+    #[test]
+    fn estimates_cct_from_the_mixed_whites() {
+        let cold = WhiteChannel::from_kelvin(6500);
+        let warm = WhiteChannel::from_kelvin(2700);
+        let budget = budget(
+            PixelLeds::Cct {
+                cold,
+                warm,
+                white_ma: [100, 50],
+            },
+            1000,
+        );
+
+        // Each white on its own draws its own current, whatever the RGB drive values add up to.
+        let cold_only = budget.estimate_ua(Rgb16::from(cold.colour()));
+        assert!(cold_only.abs_diff(2 * (1 + 100) * 1000) <= 2 * 1000);
+        let warm_only = budget.estimate_ua(Rgb16::from(warm.colour()));
+        assert!(warm_only.abs_diff(2 * (1 + 50) * 1000) <= 2 * 1000);
+    }
+
+    #[test]
+    fn saturated_cct_mixes_are_scaled_within_the_budget() {
+        let cold = WhiteChannel::from_kelvin(6500);
+        let warm = WhiteChannel::from_kelvin(2700);
+        let budget = budget(
+            PixelLeds::Cct {
+                cold,
+                warm,
+                white_ma: [100, 100],
+            },
+            150,
+        );
+
+        // Full white is beyond what the whites can mix, so both saturate.
+        let (limited, is_limited) = budget.limit(WHITE);
+        assert!(is_limited);
+        let estimate = budget.estimate_ua(limited);
+        assert!(estimate <= 150 * 1000);
+        assert!(estimate > 140 * 1000, "{estimate}");
+    }
+}
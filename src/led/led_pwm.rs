@@ -0,0 +1,187 @@
+use embedded_hal::pwm::SetDutyCycle;
+use esp_hal::gpio::DriveMode;
+use esp_hal::gpio::interconnect::PeripheralOutput;
+use esp_hal::ledc::channel::{self, Channel, ChannelIFace};
+use esp_hal::ledc::timer::{self, LSClockSource, Timer, TimerIFace};
+use esp_hal::ledc::{LSGlobalClkSource, Ledc, LowSpeed};
+use esp_hal::peripherals;
+use esp_hal::time::Rate;
+
+use crate::led::led_colour::Rgb16;
+use crate::led::led_output::{LedOutput, WhiteChannel, mix_whites};
+
+/// LEDC timer settings shared by all channels of a [`PwmOutput`].
+#[derive(Clone, Copy, Debug)]
+pub struct PwmConfig {
+    pub frequency: Rate,
+    pub resolution: timer::config::Duty,
+}
+
+impl Default for PwmConfig {
+    /// 20 kHz keeps the PWM inaudible and flicker free at a 10-bit resolution.
+    fn default() -> Self {
+        Self {
+            frequency: Rate::from_khz(20),
+            resolution: timer::config::Duty::Duty10Bit,
+        }
+    }
+}
+
+/// Initialises the LEDC peripheral for use with [`PwmOutput`].
+pub fn new_ledc(ledc: peripherals::LEDC<'_>) -> Ledc<'_> {
+    let mut ledc = Ledc::new(ledc);
+    ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+    ledc
+}
+
+/// Configures an LEDC timer to clock the channels of a [`PwmOutput`].
+pub fn new_timer<'a>(
+    ledc: &Ledc<'a>,
+    number: timer::Number,
+    config: PwmConfig,
+) -> Result<Timer<'a, LowSpeed>, timer::Error> {
+    let mut timer = ledc.timer::<LowSpeed>(number);
+    timer.configure(timer::config::Config {
+        duty: config.resolution,
+        clock_source: LSClockSource::APBClk,
+        frequency: config.frequency,
+    })?;
+    Ok(timer)
+}
+
+fn new_channel<'a>(
+    ledc: &Ledc<'a>,
+    timer: &'a Timer<'a, LowSpeed>,
+    number: channel::Number,
+    pin: impl PeripheralOutput<'a>,
+) -> Result<Channel<'a, LowSpeed>, channel::Error> {
+    let mut channel = ledc.channel(number, pin);
+    channel.configure(channel::config::Config {
+        timer,
+        duty_pct: 0,
+        drive_mode: DriveMode::PushPull,
+    })?;
+    Ok(channel)
+}
+
+/// Whether a channel's LED is lit by driving its pin high or low.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Polarity {
+    /// E.g. a low-side MOSFET switching the LED.
+    ActiveHigh,
+    /// E.g. a common-anode LED sinking directly into the pin.
+    ActiveLow,
+}
+
+/// The LED channels driven by a [`PwmOutput`].
+pub enum PwmChannels<P> {
+    /// Separate red, green and blue channels.
+    Rgb { red: P, green: P, blue: P },
+    /// Cold and warm white channels of a tunable-white LED. The colour of each is used to mix
+    /// the requested colour temperature.
+    Cct {
+        cold: P,
+        warm: P,
+        cold_white: WhiteChannel,
+        warm_white: WhiteChannel,
+    },
+}
+
+/// Drives analog LEDs with a PWM channel per colour.
+pub struct PwmOutput<P> {
+    channels: PwmChannels<P>,
+    polarity: Polarity,
+}
+
+impl<P: SetDutyCycle> PwmOutput<P> {
+    pub fn new(channels: PwmChannels<P>, polarity: Polarity) -> Self {
+        Self { channels, polarity }
+    }
+
+    /// Sets the duty cycle of `channel` to `value`, where `u16::MAX` is fully lit, rounded to
+    /// the nearest step of the timer's resolution.
+    fn set(channel: &mut P, value: u16, polarity: Polarity) -> Result<(), P::Error> {
+        let value = match polarity {
+            Polarity::ActiveHigh => value,
+            Polarity::ActiveLow => u16::MAX - value,
+        };
+        let max = channel.max_duty_cycle() as u32;
+        let duty = (value as u32 * max + u16::MAX as u32 / 2) / u16::MAX as u32;
+        channel.set_duty_cycle(duty as u16)
+    }
+
+    fn first_channel(&self) -> &P {
+        match &self.channels {
+            PwmChannels::Rgb { red, .. } => red,
+            PwmChannels::Cct { cold, .. } => cold,
+        }
+    }
+}
+
+impl<'a> PwmOutput<Channel<'a, LowSpeed>> {
+    /// An RGB LED on LEDC channels 0 to 2.
+    pub fn rgb(
+        ledc: &Ledc<'a>,
+        timer: &'a Timer<'a, LowSpeed>,
+        red: impl PeripheralOutput<'a>,
+        green: impl PeripheralOutput<'a>,
+        blue: impl PeripheralOutput<'a>,
+        polarity: Polarity,
+    ) -> Result<Self, channel::Error> {
+        let channels = PwmChannels::Rgb {
+            red: new_channel(ledc, timer, channel::Number::Channel0, red)?,
+            green: new_channel(ledc, timer, channel::Number::Channel1, green)?,
+            blue: new_channel(ledc, timer, channel::Number::Channel2, blue)?,
+        };
+        Ok(Self::new(channels, polarity))
+    }
+
+    /// A tunable-white LED on LEDC channels 0 and 1.
+    pub fn cct(
+        ledc: &Ledc<'a>,
+        timer: &'a Timer<'a, LowSpeed>,
+        cold: impl PeripheralOutput<'a>,
+        warm: impl PeripheralOutput<'a>,
+        cold_white: WhiteChannel,
+        warm_white: WhiteChannel,
+        polarity: Polarity,
+    ) -> Result<Self, channel::Error> {
+        let channels = PwmChannels::Cct {
+            cold: new_channel(ledc, timer, channel::Number::Channel0, cold)?,
+            warm: new_channel(ledc, timer, channel::Number::Channel1, warm)?,
+            cold_white,
+            warm_white,
+        };
+        Ok(Self::new(channels, polarity))
+    }
+}
+
+impl<P: SetDutyCycle> LedOutput for PwmOutput<P> {
+    type Error = P::Error;
+
+    /// The resolution of the LEDC timer, e.g. 10 bits with the default [`PwmConfig`].
+    fn bits(&self) -> u32 {
+        let max = self.first_channel().max_duty_cycle();
+        (u16::BITS - max.leading_zeros()).max(1)
+    }
+
+    async fn write(&mut self, colour: Rgb16) -> Result<(), Self::Error> {
+        match &mut self.channels {
+            PwmChannels::Rgb { red, green, blue } => {
+                Self::set(red, colour.r, self.polarity)?;
+                Self::set(green, colour.g, self.polarity)?;
+                Self::set(blue, colour.b, self.polarity)
+            }
+            PwmChannels::Cct {
+                cold,
+                warm,
+                cold_white,
+                warm_white,
+            } => {
+                let (cold_level, warm_level) = mix_whites(colour, *cold_white, *warm_white);
+                Self::set(cold, cold_level, self.polarity)?;
+                Self::set(warm, warm_level, self.polarity)
+            }
+        }
+    }
+}
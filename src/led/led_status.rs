@@ -0,0 +1,169 @@
+use core::cell::Cell;
+
+use embassy_futures::select::select;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+
+#[cfg(feature = "defmt")]
+use defmt::{error, info};
+#[cfg(feature = "log")]
+use log::{error, info};
+
+use rs_matter_embassy::matter::error::Error;
+use rs_matter_embassy::stack::persist::KvBlobStore;
+
+use crate::led::led_channel::LedSender;
+use crate::led::led_compositor::Overlay;
+use crate::led::led_driver::ControlMessage;
+use crate::led::led_indication::Indication;
+use crate::reset::Resettable;
+use crate::settings::{Decoder, Encoder, Setting, SettingKey, Settings};
+
+/// Interval at which the device status is checked.
+const POLL_PERIOD: Duration = Duration::from_millis(500);
+
+/// Commissioning and network state of the device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceStatus {
+    /// The commissioning window is open, waiting for a controller to commission the device.
+    Commissioning,
+    /// Not commissioned, with the commissioning window closed, e.g. after it timed out.
+    Uncommissioned,
+    /// Commissioned, and connecting to the network since the device started.
+    Joining,
+    /// Commissioned and connected to the network.
+    Connected,
+    /// Commissioned, but the network is lost.
+    NetworkLost,
+}
+
+/// Whether the status patterns are hidden, saved by [`persist_status_suppressed`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatusSuppressed(pub bool);
+
+impl Setting for StatusSuppressed {
+    const KEY: SettingKey = SettingKey::StatusSuppressed;
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut encoder = Encoder::new(buf);
+        encoder.put(&[self.0 as u8])?;
+        Ok(encoder.written())
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        match Decoder::new(data).take::<1>()? {
+            [0] => Some(Self(false)),
+            [1] => Some(Self(true)),
+            _ => None,
+        }
+    }
+}
+
+/// Shows the [`DeviceStatus`] on the LED with the standard [`Indication`] patterns, using the
+/// [`Overlay::Status`] layer.
+pub struct StatusIndicator<'a> {
+    sender: LedSender<'a>,
+    suppressed: Mutex<CriticalSectionRawMutex, Cell<bool>>,
+    changed: Signal<CriticalSectionRawMutex, ()>,
+    suppressed_updates: Signal<CriticalSectionRawMutex, StatusSuppressed>,
+}
+
+impl<'a> StatusIndicator<'a> {
+    pub fn new(sender: LedSender<'a>) -> Self {
+        Self {
+            sender,
+            suppressed: Mutex::new(Cell::new(false)),
+            changed: Signal::new(),
+            suppressed_updates: Signal::new(),
+        }
+    }
+
+    /// Restores the suppression saved before the last restart.
+    pub fn with_suppressed(self, saved: StatusSuppressed) -> Self {
+        self.suppressed.lock(|cell| cell.set(saved.0));
+        self
+    }
+
+    /// Stops showing status patterns, e.g. once the lamp is in normal use, or resumes them.
+    /// The choice is saved.
+    pub fn set_suppressed(&self, suppressed: bool) {
+        self.suppressed.lock(|cell| cell.set(suppressed));
+        self.changed.signal(());
+        self.suppressed_updates.signal(StatusSuppressed(suppressed));
+    }
+
+    pub fn is_suppressed(&self) -> bool {
+        self.suppressed.lock(|cell| cell.get())
+    }
+
+    /// Polls `status` and updates the LED whenever the status changes.
+    pub async fn run(&self, mut status: impl FnMut() -> DeviceStatus) -> ! {
+        let mut previous = None;
+        let mut suppressed = false;
+
+        loop {
+            let current = status();
+            let now_suppressed = self.is_suppressed();
+
+            if previous != Some(current) || suppressed != now_suppressed {
+                info!(
+                    "Device status: {}",
+                    match current {
+                        DeviceStatus::Commissioning => "commissioning",
+                        DeviceStatus::Uncommissioned => "uncommissioned",
+                        DeviceStatus::Joining => "joining",
+                        DeviceStatus::Connected => "connected",
+                        DeviceStatus::NetworkLost => "network lost",
+                    }
+                );
+
+                let indication = match current {
+                    _ if now_suppressed => None,
+                    DeviceStatus::Commissioning => Some(Indication::Commissioning),
+                    DeviceStatus::NetworkLost => Some(Indication::NetworkLost),
+                    DeviceStatus::Connected if previous == Some(DeviceStatus::Commissioning) => {
+                        Some(Indication::Commissioned)
+                    }
+                    // The light restored at start-up is shown while the network comes up.
+                    DeviceStatus::Joining | DeviceStatus::Connected => None,
+                    DeviceStatus::Uncommissioned => None,
+                };
+
+                self.sender.send(ControlMessage::Indicate {
+                    overlay: Overlay::Status,
+                    indication,
+                });
+
+                previous = Some(current);
+                suppressed = now_suppressed;
+            }
+
+            select(Timer::after(POLL_PERIOD), self.changed.wait()).await;
+        }
+    }
+}
+
+impl Resettable for StatusIndicator<'_> {
+    /// Shows the status patterns again. The saved setting is removed along with the others.
+    fn reset(&self) {
+        self.suppressed.lock(|cell| cell.set(false));
+        self.changed.signal(());
+    }
+}
+
+/// Saves the suppression whenever it is changed through [`StatusIndicator::set_suppressed`].
+pub async fn persist_status_suppressed<S: KvBlobStore>(
+    settings: &Settings<S>,
+    indicator: &StatusIndicator<'_>,
+) -> ! {
+    loop {
+        let suppressed = indicator.suppressed_updates.wait().await;
+
+        match settings.save(&suppressed).await {
+            Ok(()) => info!("Saved status suppression"),
+            Err(e) => error!("Failed to save status suppression: {}", e),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use embassy_time::{Duration, Instant};
+
+use crate::led::led_colour::Rgb16;
+
+/// A value that can be linearly interpolated between two end points.
+pub trait Interpolate: Copy {
+    /// Returns the value `fraction / u16::MAX` of the way from `from` to `to`.
+    fn interpolate(from: Self, to: Self, fraction: u16) -> Self;
+}
+
+impl Interpolate for u8 {
+    fn interpolate(from: Self, to: Self, fraction: u16) -> Self {
+        let from = from as i32;
+        let to = to as i32;
+        (from + (to - from) * fraction as i32 / u16::MAX as i32) as u8
+    }
+}
+
+impl Interpolate for u16 {
+    fn interpolate(from: Self, to: Self, fraction: u16) -> Self {
+        let from = from as i64;
+        let to = to as i64;
+        (from + (to - from) * fraction as i64 / u16::MAX as i64) as u16
+    }
+}
+
+impl Interpolate for Rgb16 {
+    fn interpolate(from: Self, to: Self, fraction: u16) -> Self {
+        Rgb16 {
+            r: u16::interpolate(from.r, to.r, fraction),
+            g: u16::interpolate(from.g, to.g, fraction),
+            b: u16::interpolate(from.b, to.b, fraction),
+        }
+    }
+}
+
+/// A value moving linearly from a start point to a target over a fixed duration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transition<T> {
+    start: T,
+    target: T,
+    started: Instant,
+    duration: Duration,
+}
+
+impl<T: Interpolate> Transition<T> {
+    /// A transition that has already reached `value`.
+    pub fn fixed(value: T) -> Self {
+        Self {
+            start: value,
+            target: value,
+            started: Instant::from_ticks(0),
+            duration: Duration::from_ticks(0),
+        }
+    }
+
+    /// Starts a transition from `start` to `target`, beginning now.
+    pub fn new(start: T, target: T, duration: Duration) -> Self {
+        Self {
+            start,
+            target,
+            started: Instant::now(),
+            duration,
+        }
+    }
+
+    /// Starts a new transition towards `target` from wherever this one currently is.
+    pub fn retarget(&self, target: T, duration: Duration) -> Self {
+        Self::new(self.value_at(Instant::now()), target, duration)
+    }
+
+    pub fn target(&self) -> T {
+        self.target
+    }
+
+    /// The time at which the transition reaches its target.
+    pub fn end(&self) -> Instant {
+        self.started.saturating_add(self.duration)
+    }
+
+    /// The time left until the target is reached.
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.end().saturating_duration_since(now)
+    }
+
+    pub fn is_finished(&self, now: Instant) -> bool {
+        now >= self.end()
+    }
+
+    /// The interpolated value at `now`.
+    pub fn value_at(&self, now: Instant) -> T {
+        if self.is_finished(now) {
+            return self.target;
+        }
+
+        let elapsed = now.saturating_duration_since(self.started).as_ticks();
+        let fraction = elapsed * u16::MAX as u64 / self.duration.as_ticks();
+        T::interpolate(self.start, self.target, fraction as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::at;
+
+    const DURATION: Duration = Duration::from_millis(1000);
+
+    fn transition<T: Interpolate>(start: T, target: T) -> Transition<T> {
+        Transition {
+            start,
+            target,
+            started: at(500),
+            duration: DURATION,
+        }
+    }
+
+    #[test]
+    fn starts_at_the_start_point() {
+        let transition = transition(10u8, 250);
+
+        assert_eq!(transition.value_at(at(0)), 10);
+        assert_eq!(transition.value_at(at(500)), 10);
+        assert!(!transition.is_finished(at(500)));
+        assert_eq!(transition.remaining(at(500)), DURATION);
+    }
+
+    #[test]
+    fn reaches_the_target_at_full_duration() {
+        let transition = transition(10u8, 250);
+
+        assert_eq!(transition.value_at(at(1499)), 249);
+        assert_eq!(transition.value_at(at(1500)), 250);
+        assert_eq!(transition.value_at(at(5000)), 250);
+        assert!(transition.is_finished(at(1500)));
+        assert_eq!(transition.remaining(at(1500)), Duration::from_ticks(0));
+    }
+
+    #[test]
+    fn interpolates_each_channel() {
+        let transition = transition(Rgb16::new(0, u16::MAX, 1000), Rgb16::new(u16::MAX, 0, 1000));
+
+        assert_eq!(
+            transition.value_at(at(1000)),
+            Rgb16::new(32767, 32768, 1000)
+        );
+    }
+
+    #[test]
+    fn fixed_is_already_finished() {
+        let transition = Transition::fixed(42u16);
+
+        assert!(transition.is_finished(at(0)));
+        assert_eq!(transition.value_at(at(0)), 42);
+        assert_eq!(transition.target(), 42);
+    }
+}
@@ -1,2 +1,17 @@
+pub mod led_attributes;
+pub mod led_calibration;
+pub mod led_channel;
+pub mod led_colour;
+pub mod led_compositor;
+pub mod led_curve;
+pub mod led_dither;
 pub mod led_driver;
+pub mod led_frame;
 pub mod led_handler;
+pub mod led_indication;
+pub mod led_output;
+pub mod led_power;
+#[cfg(feature = "esp")]
+pub mod led_pwm;
+pub mod led_status;
+pub mod led_transition;
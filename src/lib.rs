@@ -1,4 +1,10 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 pub mod dm;
+pub mod input;
 pub mod led;
+pub mod reset;
+pub mod settings;
+
+#[cfg(test)]
+mod test_util;
@@ -0,0 +1,15 @@
+/// Application state returned to its factory defaults by a factory reset, alongside the Matter
+/// stack's own state.
+pub trait Resettable {
+    /// Returns the state to its defaults. Saved copies of the state are left to the caller.
+    fn reset(&self);
+}
+
+impl<T> Resettable for &T
+where
+    T: Resettable,
+{
+    fn reset(&self) {
+        (*self).reset()
+    }
+}
@@ -0,0 +1,168 @@
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+
+#[cfg(feature = "defmt")]
+use defmt::warn;
+#[cfg(feature = "log")]
+use log::warn;
+
+use rs_matter_embassy::matter::error::{Error, ErrorCode};
+use rs_matter_embassy::stack::persist::KvBlobStore;
+
+/// Size of the scratch buffer used to load and store a single setting.
+const BUF_SIZE: usize = 256;
+
+/// Keys of the application settings in the [`KvBlobStore`], reserved from `0x1000` upwards,
+/// well clear of the keys used by the Matter stack.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u16)]
+pub enum SettingKey {
+    Calibration = 0x1000,
+    KnobRange = 0x1001,
+    LightAttributes = 0x1002,
+    BrightnessCurve = 0x1003,
+    StatusSuppressed = 0x1004,
+}
+
+/// A value persisted in the application [`Settings`].
+pub trait Setting: Sized {
+    const KEY: SettingKey;
+
+    /// Writes the value to `buf`, returning the number of bytes written.
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, Error>;
+
+    /// Reads a value previously written by [`Setting::encode`].
+    fn decode(data: &[u8]) -> Option<Self>;
+}
+
+/// Application settings, kept in the same [`KvBlobStore`] as the Matter stack's state, which
+/// accesses it through [`Settings::shared_store`].
+pub struct Settings<S> {
+    store: Mutex<CriticalSectionRawMutex, S>,
+}
+
+impl<S: KvBlobStore> Settings<S> {
+    pub const fn new(store: S) -> Self {
+        Self {
+            store: Mutex::new(store),
+        }
+    }
+
+    /// A handle to the underlying store for the Matter stack, taking turns with the settings.
+    pub fn shared_store(&self) -> SharedStore<'_, S> {
+        SharedStore { store: &self.store }
+    }
+
+    /// Loads a setting, returning `None` if it was never saved or cannot be read.
+    pub async fn load<T: Setting>(&self) -> Option<T> {
+        let mut store = self.store.lock().await;
+        let mut buf = [0; BUF_SIZE];
+        let mut value = None;
+
+        let result = store
+            .load(T::KEY as u16, &mut buf, |data| {
+                value = data.and_then(T::decode);
+                Ok(())
+            })
+            .await;
+
+        if let Err(e) = result {
+            warn!("Failed to load setting {}: {}", T::KEY as u16, e);
+        }
+
+        value
+    }
+
+    pub async fn save<T: Setting>(&self, value: &T) -> Result<(), Error> {
+        let mut store = self.store.lock().await;
+        let mut buf = [0; BUF_SIZE];
+
+        store
+            .store(T::KEY as u16, &mut buf, |buf| value.encode(buf))
+            .await
+    }
+
+    pub async fn remove<T: Setting>(&self) -> Result<(), Error> {
+        let mut store = self.store.lock().await;
+        let mut buf = [0; BUF_SIZE];
+
+        store.remove(T::KEY as u16, &mut buf).await
+    }
+}
+
+/// The store of the [`Settings`], shared with the Matter stack.
+pub struct SharedStore<'a, S> {
+    store: &'a Mutex<CriticalSectionRawMutex, S>,
+}
+
+impl<S: KvBlobStore> KvBlobStore for SharedStore<'_, S> {
+    async fn load<F>(&mut self, key: u16, buf: &mut [u8], cb: F) -> Result<(), Error>
+    where
+        F: FnOnce(Option<&[u8]>) -> Result<(), Error>,
+    {
+        self.store.lock().await.load(key, buf, cb).await
+    }
+
+    async fn store<F>(&mut self, key: u16, buf: &mut [u8], cb: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut [u8]) -> Result<usize, Error>,
+    {
+        self.store.lock().await.store(key, buf, cb).await
+    }
+
+    async fn remove(&mut self, key: u16, buf: &mut [u8]) -> Result<(), Error> {
+        self.store.lock().await.remove(key, buf).await
+    }
+}
+
+/// Writes fixed size values into a buffer, failing once it is full.
+pub struct Encoder<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    pub fn put(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.len + bytes.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(ErrorCode::NoSpace)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    pub fn put_f32(&mut self, value: f32) -> Result<(), Error> {
+        self.put(&value.to_le_bytes())
+    }
+
+    /// The number of bytes written so far.
+    pub fn written(&self) -> usize {
+        self.len
+    }
+}
+
+/// Reads the values written by an [`Encoder`].
+pub struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (bytes, rest) = self.data.split_first_chunk::<N>()?;
+        self.data = rest;
+        Some(*bytes)
+    }
+
+    pub fn take_f32(&mut self) -> Option<f32> {
+        self.take().map(f32::from_le_bytes)
+    }
+}
@@ -0,0 +1,21 @@
+//! Helpers shared by the unit tests.
+
+use embassy_futures::block_on;
+use embassy_time::Instant;
+
+use crate::led::led_channel::LedChannel;
+use crate::led::led_driver::ControlMessage;
+
+/// The instant `millis` milliseconds after the clock started.
+pub fn at(millis: u64) -> Instant {
+    Instant::from_millis(millis)
+}
+
+/// The commands sent through `channel` and not yet picked up, in the order the driver takes them.
+pub fn drain(channel: &LedChannel) -> Vec<ControlMessage> {
+    let mut messages = Vec::new();
+    while !channel.sender().is_idle() {
+        messages.push(block_on(channel.receiver().receive()));
+    }
+    messages
+}